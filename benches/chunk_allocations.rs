@@ -0,0 +1,151 @@
+//! Counts the allocations the microphone makes collecting audio into chunks, with and without the
+//! game handing the chunks back to be filled again, and those the desktop socket thread makes
+//! packing the chunks and sending them to Deepgram. Run with `cargo bench`.
+use jamfest::microphone::Chunker;
+use jamfest::ring_buffer::RingBuffer;
+use jamfest::speech::AudioBuffer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const SAMPLE_RATE: u32 = 16_000;
+const CHUNK_SECONDS: f32 = 0.05;
+/// How much audio the microphone hands over at a time.
+const RECORDING_SECONDS: f32 = 0.01;
+/// How often the game reads the audio, at 60 frames a second.
+const FRAME_SECONDS: f32 = 1.0 / 60.0;
+const SECONDS: usize = 600;
+
+/// Play `SECONDS` of audio through a chunker, returning how many allocations it took per second.
+fn allocations_per_second(recycle: bool) -> f32 {
+    let queue = RingBuffer::new(1.0, AudioBuffer::duration);
+    let spares = RingBuffer::with_length(16);
+    let mut chunker = Chunker::new(CHUNK_SECONDS, queue.clone(), spares.clone());
+    let recording = vec![0.25; (SAMPLE_RATE as f32 * RECORDING_SECONDS) as usize];
+    let recordings = (SECONDS as f32 / RECORDING_SECONDS) as usize;
+
+    let read = |time: f32, last_read: &mut f32| {
+        if time - *last_read >= FRAME_SECONDS {
+            *last_read = time;
+            while let Some(audio) = queue.pop() {
+                if recycle {
+                    spares.push(audio.samples);
+                }
+            }
+        }
+    };
+    // let the queues grow to their working size first
+    let mut last_read = 0.0;
+    for index in 0..recordings / 10 {
+        chunker.push(SAMPLE_RATE, &recording);
+        read(index as f32 * RECORDING_SECONDS, &mut last_read);
+    }
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut last_read = 0.0;
+    for index in 0..recordings {
+        chunker.push(SAMPLE_RATE, &recording);
+        read(index as f32 * RECORDING_SECONDS, &mut last_read);
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f32 / SECONDS as f32
+}
+
+/// An in-memory socket that throws away everything written to it and never has anything to read.
+#[cfg(all(feature = "deepgram", not(target_arch = "wasm32")))]
+struct NullStream;
+
+#[cfg(all(feature = "deepgram", not(target_arch = "wasm32")))]
+impl std::io::Read for NullStream {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+}
+
+#[cfg(all(feature = "deepgram", not(target_arch = "wasm32")))]
+impl std::io::Write for NullStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Pack `SECONDS` of chunks as linear16 and send them the way the desktop socket thread does,
+/// returning how many allocations it took per message. Without `send` the chunks are only packed,
+/// into the one buffer.
+#[cfg(all(feature = "deepgram", not(target_arch = "wasm32")))]
+fn allocations_per_message(send: bool) -> f32 {
+    use jamfest::deepgram_transport::pack_linear16;
+    use tungstenite::protocol::{Role, WebSocket};
+    use tungstenite::Message;
+
+    let mut socket = WebSocket::from_raw_socket(NullStream, Role::Client, None);
+    let samples = vec![8_000i16; (SAMPLE_RATE as f32 * CHUNK_SECONDS) as usize];
+    let messages = (SECONDS as f32 / CHUNK_SECONDS) as usize;
+    let mut reused = Vec::new();
+    let mut pack_and_send = || {
+        if send {
+            let mut bytes = Vec::new();
+            pack_linear16(&samples, &mut bytes);
+            socket.write_message(Message::Binary(bytes)).unwrap();
+        } else {
+            pack_linear16(&samples, &mut reused);
+        }
+    };
+    // let the buffers grow to their working size first
+    for _ in 0..messages / 10 {
+        pack_and_send();
+    }
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..messages {
+        pack_and_send();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f32 / messages as f32
+}
+
+fn main() {
+    println!(
+        "without recycling: {:.1} allocations per second of audio",
+        allocations_per_second(false)
+    );
+    println!(
+        "with recycling:    {:.1} allocations per second of audio",
+        allocations_per_second(true)
+    );
+    #[cfg(all(feature = "deepgram", not(target_arch = "wasm32")))]
+    {
+        println!(
+            "packing:           {:.1} allocations per message",
+            allocations_per_message(false)
+        );
+        println!(
+            "packing, sending:  {:.1} allocations per message",
+            allocations_per_message(true)
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Playing a recording into the speech pipeline in place of the microphone, so that a recognition
+//! problem can be reproduced exactly, as often as needed. Desktop only, since the browser can't
+//! read files from disk.
+use super::microphone::Chunker;
+use super::resample::Resampler;
+use bevy::prelude::*;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Silence sent after the recording, so that whatever was said at the very end of it is finished
+/// rather than left waiting for more audio.
+const TRAILING_SILENCE_SECONDS: f32 = 1.0;
+/// Raw PCM files don't say what rate they were recorded at, so unless told otherwise we assume
+/// the rate we'd send to Deepgram.
+const DEFAULT_RAW_SAMPLE_RATE: u32 = 16_000;
+/// Microphones hand over audio every few milliseconds, so the recording is played in steps this
+/// long.
+const STEP_SECONDS: f32 = 0.01;
+
+/// How quickly to play a recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// As fast as it was recorded, just like the microphone.
+    RealTime,
+    /// All at once. Deepgram only keeps the last few seconds of audio sent before it connects, so
+    /// this suits short recordings, or the offline keyword spotter.
+    AsFastAsPossible,
+}
+
+/// A recording to play instead of listening to the microphone. Either a WAV file, or raw
+/// little-endian mono linear16 samples for any other extension.
+#[derive(Clone, Debug)]
+pub struct AudioFile {
+    pub path: PathBuf,
+    pub pacing: Pacing,
+    /// The sample rate of raw PCM files. WAV files have their own.
+    pub raw_sample_rate: u32,
+}
+
+impl AudioFile {
+    /// The recording given by the `JAMFEST_AUDIO_FILE` environment variable, if any. Set
+    /// `JAMFEST_AUDIO_PACING=fast` to play it as fast as possible, and `JAMFEST_AUDIO_SAMPLE_RATE`
+    /// to give the sample rate of a raw PCM file.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("JAMFEST_AUDIO_FILE")?;
+        let pacing = match std::env::var("JAMFEST_AUDIO_PACING").as_deref() {
+            Ok("fast") => Pacing::AsFastAsPossible,
+            _ => Pacing::RealTime,
+        };
+        let raw_sample_rate = std::env::var("JAMFEST_AUDIO_SAMPLE_RATE")
+            .ok()
+            .and_then(|sample_rate| sample_rate.parse().ok())
+            .unwrap_or(DEFAULT_RAW_SAMPLE_RATE);
+
+        Some(AudioFile {
+            path: path.into(),
+            pacing,
+            raw_sample_rate,
+        })
+    }
+
+    /// Read the whole recording as mono samples between -1 and 1, along with its sample rate.
+    fn read(&self) -> Result<(u32, Vec<f32>), String> {
+        let bytes = std::fs::read(&self.path).map_err(|error| error.to_string())?;
+        let is_wav = self
+            .path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+
+        if is_wav {
+            read_wav(&bytes)
+        } else {
+            let samples = bytes
+                .chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+                .collect();
+            Ok((self.raw_sample_rate, samples))
+        }
+    }
+}
+
+/// Play `file` on a thread of its own, handing it to `chunker` just as the microphone would,
+/// resampled to `target_sample_rate` if given.
+pub fn play(file: AudioFile, target_sample_rate: Option<u32>, mut chunker: Chunker) {
+    let (sample_rate, mut samples) = match file.read() {
+        Ok(recording) => recording,
+        Err(error) => {
+            error!("Could not read {}: {}", file.path.display(), error);
+            return;
+        }
+    };
+    info!(
+        "Playing {} ({:.1} seconds at {} Hz) instead of listening to the microphone.",
+        file.path.display(),
+        samples.len() as f32 / sample_rate as f32,
+        sample_rate
+    );
+    samples.resize(
+        samples.len() + (sample_rate as f32 * TRAILING_SILENCE_SECONDS) as usize,
+        0.0,
+    );
+
+    thread::spawn(move || {
+        let mut resampler = target_sample_rate
+            .filter(|&target| target != sample_rate)
+            .map(|target| Resampler::new(sample_rate, target));
+        // paced in steps the length of a microphone recording
+        let step_length = ((sample_rate as f32 * STEP_SECONDS) as usize).max(1);
+        let mut resampled = Vec::new();
+        let started = Instant::now();
+
+        for (index, step) in samples.chunks(step_length).enumerate() {
+            if file.pacing == Pacing::RealTime {
+                let due =
+                    Duration::from_secs_f32(index as f32 * step_length as f32 / sample_rate as f32);
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+
+            if chunker.is_abandoned() {
+                return;
+            }
+            match &mut resampler {
+                Some(resampler) => {
+                    resampled.clear();
+                    resampler.process(step, &mut resampled);
+                    chunker.push(resampler.output_rate(), &resampled);
+                }
+                None => chunker.push(sample_rate, step),
+            }
+        }
+
+        info!("Finished playing {}.", file.path.display());
+    });
+}
+
+/// Decode a WAV file's integer or float PCM, mixing its channels down to mono.
+pub(crate) fn read_wav(bytes: &[u8]) -> Result<(u32, Vec<f32>), String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let length = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let body = &rest[8..length.saturating_add(8).min(rest.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // chunks are padded to an even length
+        rest = &rest[length.saturating_add(8 + length % 2).min(rest.len())..];
+    }
+    let format = format.ok_or("missing fmt chunk")?;
+    let data = data.ok_or("missing data chunk")?;
+
+    let mut encoding = u16::from_le_bytes([format[0], format[1]]);
+    let channels = u16::from_le_bytes([format[2], format[3]]).max(1) as usize;
+    let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+    let bits = u16::from_le_bytes([format[14], format[15]]);
+    // WAVE_FORMAT_EXTENSIBLE keeps the real encoding at the start of its subformat GUID
+    if encoding == 0xFFFE && format.len() >= 26 {
+        encoding = u16::from_le_bytes([format[24], format[25]]);
+    }
+    if sample_rate == 0 {
+        return Err("sample rate is zero".to_string());
+    }
+
+    let decode: fn(&[u8]) -> f32 = match (encoding, bits) {
+        (1, 8) => |s| (s[0] as f32 - 128.0) / 128.0,
+        (1, 16) => |s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0,
+        (1, 24) => |s| i32::from_le_bytes([0, s[0], s[1], s[2]]) as f32 / 2_147_483_648.0,
+        (1, 32) => |s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2_147_483_648.0,
+        (3, 32) => |s| f32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+        _ => {
+            return Err(format!(
+                "unsupported encoding {} with {} bits per sample",
+                encoding, bits
+            ))
+        }
+    };
+
+    let frame_length = channels * bits as usize / 8;
+    let samples = data
+        .chunks_exact(frame_length)
+        .map(|frame| {
+            frame
+                .chunks_exact(bits as usize / 8)
+                .map(decode)
+                .sum::<f32>()
+                / channels as f32
+        })
+        .collect();
+
+    Ok((sample_rate, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16_000;
+
+    /// A chunk with its header, padded to an even length.
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn fmt(encoding: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let frame_length = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&encoding.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        body.extend_from_slice(&(SAMPLE_RATE * frame_length as u32).to_le_bytes());
+        body.extend_from_slice(&frame_length.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        chunk(b"fmt ", &body)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    fn pcm16(samples: &[i16]) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn integer_pcm_is_decoded_at_every_bit_depth() {
+        let cases: [(u16, Vec<u8>); 4] = [
+            (8, vec![128, 192, 0]),
+            (16, pcm16(&[0, 16384, -32768])),
+            (24, vec![0, 0, 0, 0, 0, 0x40, 0, 0, 0x80]),
+            (
+                32,
+                [0i32, 1 << 30, i32::MIN]
+                    .iter()
+                    .flat_map(|s| s.to_le_bytes())
+                    .collect(),
+            ),
+        ];
+        for (bits, data) in cases {
+            let bytes = wav(&[fmt(1, 1, bits), chunk(b"data", &data)]);
+            assert_eq!(
+                read_wav(&bytes),
+                Ok((SAMPLE_RATE, vec![0.0, 0.5, -1.0])),
+                "{} bits",
+                bits
+            );
+        }
+    }
+
+    #[test]
+    fn float_pcm_is_decoded() {
+        let data: Vec<u8> = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let bytes = wav(&[fmt(3, 1, 32), chunk(b"data", &data)]);
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.25, -0.75])));
+    }
+
+    #[test]
+    fn extensible_format_uses_its_subformat() {
+        let mut extensible = fmt(0xFFFE, 1, 32)[8..].to_vec();
+        // the size of the extension, the valid bits per sample and the channel mask
+        extensible.extend_from_slice(&22u16.to_le_bytes());
+        extensible.extend_from_slice(&32u16.to_le_bytes());
+        extensible.extend_from_slice(&4u32.to_le_bytes());
+        // KSDATAFORMAT_SUBTYPE_IEEE_FLOAT, which starts with the float encoding
+        extensible.extend_from_slice(&[
+            3, 0, 0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71,
+        ]);
+        let data = 0.5f32.to_le_bytes();
+        let bytes = wav(&[chunk(b"fmt ", &extensible), chunk(b"data", &data)]);
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.5])));
+    }
+
+    #[test]
+    fn stereo_is_mixed_down_to_mono() {
+        let data = pcm16(&[16384, -16384, 16384, 16384, -32768, -32768]);
+        let bytes = wav(&[fmt(1, 2, 16), chunk(b"data", &data)]);
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.0, 0.5, -1.0])));
+    }
+
+    #[test]
+    fn other_chunks_are_skipped_including_padded_ones() {
+        let bytes = wav(&[
+            chunk(b"LIST", b"odd"),
+            fmt(1, 1, 16),
+            chunk(b"junk", &[1]),
+            chunk(b"data", &pcm16(&[16384])),
+        ]);
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.5])));
+    }
+
+    #[test]
+    fn chunk_lengths_past_the_end_are_cut_short() {
+        let mut bytes = wav(&[fmt(1, 1, 16), chunk(b"data", &pcm16(&[16384, 0]))]);
+        let data_length = bytes.len() - 8;
+        bytes[data_length..data_length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.5, 0.0])));
+
+        let mut bytes = wav(&[fmt(1, 1, 16), chunk(b"data", &pcm16(&[16384]))]);
+        bytes.extend_from_slice(b"junk");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_wav(&bytes), Ok((SAMPLE_RATE, vec![0.5])));
+    }
+
+    /// A file cut off anywhere decodes what's there, or is an error if it's cut off before its
+    /// samples start, but never panics.
+    #[test]
+    fn truncated_files_do_not_panic() {
+        let bytes = wav(&[fmt(1, 2, 24), chunk(b"data", &[0x40; 18])]);
+        let data_start = bytes.len() - 18;
+        for length in 0..bytes.len() {
+            let result = read_wav(&bytes[..length]);
+            if length < data_start {
+                assert!(result.is_err(), "cut off at {}", length);
+            } else {
+                // only whole frames are decoded
+                let (_, samples) = result.expect("the samples that are there");
+                assert_eq!(
+                    samples.len(),
+                    (length - data_start) / 6,
+                    "cut off at {}",
+                    length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_files_are_errors() {
+        let data = chunk(b"data", &pcm16(&[0]));
+        assert!(read_wav(b"RIFF\0\0\0\0AVI ").is_err());
+        assert!(read_wav(&wav(std::slice::from_ref(&data))).is_err());
+        assert!(read_wav(&wav(&[fmt(1, 1, 16)])).is_err());
+        // ADPCM, and 12 bit integers
+        assert!(read_wav(&wav(&[fmt(2, 1, 4), data.clone()])).is_err());
+        assert!(read_wav(&wav(&[fmt(1, 1, 12), data.clone()])).is_err());
+
+        let mut silent = fmt(1, 1, 16);
+        silent[12..16].copy_from_slice(&0u32.to_le_bytes());
+        assert!(read_wav(&wav(&[silent, data])).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Runs the mock Deepgram listen server from `jamfest::deepgram_mock`, for trying the game's speech
+//! handling without an API key or a network connection.
+//!
+//! ```shell
+//! cargo run --bin deepgram-mock --features mock -- --listen 127.0.0.1:8082 --script script.txt
+//! DEEPGRAM_LISTEN_URL=ws://127.0.0.1:8082/v1/listen DEEPGRAM_API_KEY=mock cargo run
+//! ```
+use bevy::log::LogPlugin;
+use bevy::prelude::App;
+use jamfest::deepgram_mock::{MockServer, Script, LISTEN_PATH};
+
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:8082";
+
+#[derive(Clone, Debug)]
+struct Config {
+    listen_address: String,
+    /// The key clients must present, or `None` to accept any.
+    api_key: Option<String>,
+    script: Option<Script>,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Config {
+            listen_address: DEFAULT_LISTEN_ADDRESS.to_string(),
+            api_key: None,
+            script: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--listen" => config.listen_address = value()?,
+                "--key" => config.api_key = Some(value()?),
+                "--script" => {
+                    let path = value()?;
+                    let script = std::fs::read_to_string(&path)
+                        .map_err(|error| format!("could not read {}: {}", path, error))?;
+                    config.script = Some(Script::parse(&script)?);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("usage: deepgram-mock [--listen ADDRESS] [--key KEY] [--script FILE]");
+            std::process::exit(2);
+        }
+    };
+
+    // the server logs each connection through bevy, which needs somewhere to send the logs
+    App::new().add_plugin(LogPlugin);
+
+    let server = match MockServer::bind(&config.listen_address, config.api_key, config.script) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("Could not listen on {}: {}", config.listen_address, error);
+            std::process::exit(1);
+        }
+    };
+    match server.local_addr() {
+        Ok(address) => println!("Listening on ws://{}{}.", address, LISTEN_PATH),
+        Err(error) => eprintln!("Could not read the listening address: {}", error),
+    }
+    server.run();
+}
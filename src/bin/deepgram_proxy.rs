@@ -0,0 +1,80 @@
+//! Runs the credential proxy from `jamfest::deepgram_proxy`, which holds the Deepgram API key so
+//! that the game doesn't have to.
+//!
+//! ```shell
+//! DEEPGRAM_API_KEY=YOUR_KEY cargo run --bin deepgram-proxy --features proxy -- \
+//!     --listen 127.0.0.1:8081 --upstream wss://api.deepgram.com
+//! ```
+//!
+//! Point `--upstream` at a local `ws://` server to try the proxy out without a key.
+use bevy::log::LogPlugin;
+use bevy::prelude::App;
+use jamfest::deepgram_proxy::{ProxyConfig, ProxyServer, DEFAULT_UPSTREAM, FORWARDED_PATH};
+
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:8081";
+
+#[derive(Clone, Debug)]
+struct Config {
+    listen_address: String,
+    proxy: ProxyConfig,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let api_key = std::env::var("DEEPGRAM_API_KEY")
+            .map_err(|_| "DEEPGRAM_API_KEY must be set".to_string())?;
+        let mut config = Config {
+            listen_address: DEFAULT_LISTEN_ADDRESS.to_string(),
+            proxy: ProxyConfig::new(DEFAULT_UPSTREAM, &api_key),
+        };
+        config.proxy.secret = std::env::var("DEEPGRAM_PROXY_SECRET").ok();
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--listen" => config.listen_address = value()?,
+                "--upstream" => config.proxy.upstream = value()?.trim_end_matches('/').to_string(),
+                "--allow-origin" => config.proxy.allowed_origins.push(value()?),
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!(
+                "usage: deepgram-proxy [--listen ADDRESS] [--upstream URL] [--allow-origin ORIGIN]..."
+            );
+            std::process::exit(2);
+        }
+    };
+
+    // the proxy logs each connection through bevy, which needs somewhere to send the logs
+    App::new().add_plugin(LogPlugin);
+
+    let server = match ProxyServer::bind(&config.listen_address, config.proxy.clone()) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("Could not listen on {}: {}", config.listen_address, error);
+            std::process::exit(1);
+        }
+    };
+    match server.local_addr() {
+        Ok(address) => println!(
+            "Forwarding ws://{}{} to {}.",
+            address, FORWARDED_PATH, config.proxy.upstream
+        ),
+        Err(error) => eprintln!("Could not read the listening address: {}", error),
+    }
+    if config.proxy.secret.is_none() {
+        println!("DEEPGRAM_PROXY_SECRET isn't set, so clients don't need a secret to connect.");
+    }
+    server.run();
+}
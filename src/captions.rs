@@ -0,0 +1,206 @@
+//! Captions of what the recognizer heard, so that the player can tell a keyword that wasn't heard
+//! from one that was heard as something else.
+use super::keywords::KeywordMatcher;
+use super::speech::TranscriptEvent;
+use super::Keyword;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const TOGGLE_KEY: KeyCode = KeyCode::C;
+/// How long captions take to fade out, once they start to.
+const FADE_SECONDS: f32 = 1.0;
+/// Older words scroll off the front of the caption beyond this many.
+const MAX_CAPTION_WORDS: usize = 12;
+const CAPTION_WIDTH: f32 = 480.0;
+
+const FINAL_COLOR: Color = Color::WHITE;
+/// Interim words may still be revised, so they're shown greyed out like tentative keywords.
+const INTERIM_COLOR: Color = Color::GRAY;
+const KEYWORD_COLOR: Color = Color::YELLOW;
+
+/// Whether captions are shown, and for how long. Insert this resource before adding the
+/// `CaptionPlugin` to change it. Captions can also be toggled with `TOGGLE_KEY`.
+#[derive(Clone, Debug)]
+pub struct CaptionSettings {
+    pub enabled: bool,
+    /// How long after the last thing heard the captions start to fade.
+    pub fade_after_seconds: f32,
+}
+
+impl Default for CaptionSettings {
+    fn default() -> Self {
+        CaptionSettings {
+            enabled: true,
+            fade_after_seconds: 4.0,
+        }
+    }
+}
+
+pub struct CaptionPlugin;
+
+impl Plugin for CaptionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CaptionSettings>()
+            .init_resource::<Captions>()
+            .add_startup_system(spawn_caption_text)
+            .add_system(toggle_captions)
+            .add_system(update_captions)
+            .add_system(show_captions);
+    }
+}
+
+struct CaptionWord {
+    text: String,
+    /// Whether the word is part of a puzzle keyword.
+    is_keyword: bool,
+}
+
+/// The rolling transcript: the last few finalized words, followed by whatever the recognizer
+/// currently thinks is being said.
+#[derive(Default)]
+struct Captions {
+    final_words: VecDeque<CaptionWord>,
+    interim_words: Vec<CaptionWord>,
+    /// When we last heard a word, in seconds since startup.
+    last_heard: f64,
+}
+
+#[derive(Component)]
+struct CaptionText;
+
+fn spawn_caption_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("kongtext.ttf"),
+                    font_size: 12.0,
+                    color: FINAL_COLOR,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(8.0),
+                    bottom: Val::Px(8.0),
+                    ..default()
+                },
+                max_size: Size {
+                    width: Val::Px(CAPTION_WIDTH),
+                    height: Val::Auto,
+                },
+                ..default()
+            }),
+        )
+        .insert(CaptionText);
+}
+
+fn toggle_captions(keys: Res<Input<KeyCode>>, mut settings: ResMut<CaptionSettings>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        settings.enabled = !settings.enabled;
+        info!("Captions {}.", if settings.enabled { "on" } else { "off" });
+    }
+}
+
+fn update_captions(
+    time: Res<Time>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    mut captions: ResMut<Captions>,
+    mut transcript_events: EventReader<TranscriptEvent>,
+) {
+    for TranscriptEvent(transcript) in transcript_events.iter() {
+        let mut words: Vec<CaptionWord> = transcript
+            .words
+            .iter()
+            .map(|word| CaptionWord {
+                text: word.word.clone(),
+                is_keyword: false,
+            })
+            .collect();
+        if let Some(keyword_matcher) = &keyword_matcher {
+            let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+            for keyword_match in keyword_matcher.find_matches(&texts) {
+                if Keyword::from_name(keyword_match.keyword).is_some() {
+                    let matched = keyword_match.word_index
+                        ..keyword_match.word_index + keyword_match.word_count;
+                    for word in &mut words[matched] {
+                        word.is_keyword = true;
+                    }
+                }
+            }
+        }
+
+        // Deepgram finalizes silence as an empty transcript, which shouldn't keep captions up
+        if !words.is_empty() {
+            captions.last_heard = time.seconds_since_startup();
+        }
+        if transcript.is_final {
+            captions.interim_words.clear();
+            captions.final_words.extend(words);
+            while captions.final_words.len() > MAX_CAPTION_WORDS {
+                captions.final_words.pop_front();
+            }
+        } else {
+            captions.interim_words = words;
+        }
+    }
+}
+
+fn show_captions(
+    time: Res<Time>,
+    settings: Res<CaptionSettings>,
+    mut captions: ResMut<Captions>,
+    mut text_query: Query<&mut Text, With<CaptionText>>,
+) {
+    let mut text = text_query.single_mut();
+
+    let age = (time.seconds_since_startup() - captions.last_heard) as f32;
+    let fade = ((age - settings.fade_after_seconds) / FADE_SECONDS).clamp(0.0, 1.0);
+    if fade >= 1.0 {
+        captions.final_words.clear();
+        captions.interim_words.clear();
+    }
+    if !settings.enabled || fade >= 1.0 {
+        if !(text.sections.len() == 1 && text.sections[0].value.is_empty()) {
+            text.sections.truncate(1);
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    // interim words may push some of the final ones off the front too
+    let interim_words = &captions.interim_words[captions
+        .interim_words
+        .len()
+        .saturating_sub(MAX_CAPTION_WORDS)..];
+    let skipped =
+        (captions.final_words.len() + interim_words.len()).saturating_sub(MAX_CAPTION_WORDS);
+    let words = captions
+        .final_words
+        .iter()
+        .skip(skipped)
+        .map(|word| (word, FINAL_COLOR))
+        .chain(interim_words.iter().map(|word| (word, INTERIM_COLOR)));
+
+    let style = text.sections[0].style.clone();
+    text.sections = words
+        .map(|(word, color)| {
+            let color = if word.is_keyword {
+                KEYWORD_COLOR
+            } else {
+                color
+            };
+            TextSection::new(
+                format!("{} ", word.text),
+                TextStyle {
+                    color: *color.clone().set_a(color.a() * (1.0 - fade)),
+                    ..style.clone()
+                },
+            )
+        })
+        .collect();
+    if text.sections.is_empty() {
+        text.sections.push(TextSection::new("", style));
+    }
+}
@@ -1,11 +1,16 @@
-use super::SpeechEvent;
+use super::{Keyword, SpeechEvent};
 use bevy::prelude::*;
 
+#[cfg(feature = "speech")]
+mod console;
+
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(keyboard_input);
+        #[cfg(feature = "speech")]
+        app.add_plugin(console::ConsolePlugin);
     }
 }
 
@@ -14,12 +19,12 @@ impl Plugin for DebugPlugin {
 fn keyboard_input(keys: Res<Input<KeyCode>>, mut speech_events: EventWriter<SpeechEvent>) {
     if keys.just_pressed(KeyCode::J) {
         info!("Sending sugar speech event triggered by key press");
-        speech_events.send(SpeechEvent::Sugar);
+        speech_events.send(SpeechEvent::from_key_press(Keyword::Sugar));
     } else if keys.just_pressed(KeyCode::B) {
         info!("Sending bridge speech event triggered by key press");
-        speech_events.send(SpeechEvent::Bridge);
+        speech_events.send(SpeechEvent::from_key_press(Keyword::Bridge));
     } else if keys.just_pressed(KeyCode::M) {
         info!("Sending mentos speech event triggered by key press");
-        speech_events.send(SpeechEvent::Mentos);
+        speech_events.send(SpeechEvent::from_key_press(Keyword::Mentos));
     }
 }
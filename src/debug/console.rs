@@ -0,0 +1,422 @@
+//! A console for typing transcripts straight into the keyword matcher, for trying out new
+//! vocabulary and aliases without having to say them. What's typed goes through the same matching
+//! as Deepgram's transcripts, and the console shows which speech events it produced and why.
+use crate::keywords::KeywordMatcher;
+use crate::speech::{keyword_speech_events, Transcript, TranscriptEvent};
+use crate::typed_input::TypedInputSystem;
+use crate::{Keyword, SpeechEvent, SpeechSource};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const TOGGLE_KEY: KeyCode = KeyCode::Grave;
+const SUBMIT_KEY: KeyCode = KeyCode::Return;
+const DELETE_KEY: KeyCode = KeyCode::Back;
+const CLOSE_KEY: KeyCode = KeyCode::Escape;
+const COMPLETE_KEY: KeyCode = KeyCode::Tab;
+const OLDER_KEY: KeyCode = KeyCode::Up;
+const NEWER_KEY: KeyCode = KeyCode::Down;
+/// How many lines of output are shown, above the input line.
+const MAX_OUTPUT_LINES: usize = 12;
+const MAX_HISTORY: usize = 50;
+const MAX_INPUT_LENGTH: usize = 80;
+
+const INPUT_COLOR: Color = Color::WHITE;
+const EVENT_COLOR: Color = Color::GREEN;
+const INFO_COLOR: Color = Color::GRAY;
+const WARNING_COLOR: Color = Color::YELLOW;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Console>()
+            .add_startup_system(spawn_console)
+            // like the typed input box, the console takes the keys before anything else sees
+            // them, and before the typed input box too so that Enter doesn't open it
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                use_console.after(InputSystem).before(TypedInputSystem),
+            )
+            .add_system(show_console);
+    }
+}
+
+#[derive(Default)]
+struct Console {
+    open: bool,
+    input: String,
+    /// What's been entered, oldest first.
+    history: Vec<String>,
+    /// Where we are in the history while walking through it with the arrow keys.
+    history_index: Option<usize>,
+    output: VecDeque<(String, Color)>,
+}
+
+impl Console {
+    fn print(&mut self, line: impl Into<String>, color: Color) {
+        self.output.push_back((line.into(), color));
+        while self.output.len() > MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    fn older(&mut self) {
+        let index = match self.history_index {
+            Some(index) => index.saturating_sub(1),
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.history_index = Some(index);
+        self.input = self.history[index].clone();
+    }
+
+    fn newer(&mut self) {
+        match self.history_index {
+            Some(index) if index + 1 < self.history.len() => {
+                self.history_index = Some(index + 1);
+                self.input = self.history[index + 1].clone();
+            }
+            Some(_) => {
+                self.history_index = None;
+                self.input.clear();
+            }
+            None => {}
+        }
+    }
+
+    /// Complete the last word to a keyword or alias. When several fit, it's completed as far as
+    /// they agree and they're all listed.
+    fn complete(&mut self, keyword_matcher: &KeywordMatcher) {
+        let word = self.input.rsplit(char::is_whitespace).next().unwrap_or("");
+        let start = self.input.len() - word.len();
+        let prefix = word.to_lowercase();
+        if prefix.is_empty() {
+            return;
+        }
+
+        let mut candidates: Vec<&str> = keyword_matcher
+            .spellings()
+            .filter(|spelling| spelling.starts_with(&prefix))
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let completion = match candidates.as_slice() {
+            [] => return,
+            [only] => format!("{} ", only),
+            [first, rest @ ..] => {
+                // counted in characters, so that the completion never ends partway through one
+                let common = rest
+                    .iter()
+                    .fold(first.chars().count(), |length, candidate| {
+                        first
+                            .chars()
+                            .zip(candidate.chars())
+                            .take(length)
+                            .take_while(|(a, b)| a == b)
+                            .count()
+                    });
+                self.print(candidates.join("  "), INFO_COLOR);
+                first.chars().take(common).collect()
+            }
+        };
+        self.input.truncate(start);
+        self.input.push_str(&completion);
+    }
+}
+
+#[derive(Component)]
+struct ConsolePanel;
+
+#[derive(Component)]
+struct ConsoleText;
+
+/// The console's text is rebuilt whenever it changes, so it keeps hold of its font.
+struct ConsoleFont(Handle<Font>);
+
+fn spawn_console(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(0.0),
+                    right: Val::Px(0.0),
+                    top: Val::Px(0.0),
+                    ..default()
+                },
+                padding: UiRect::all(Val::Px(4.0)),
+                ..default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(ConsolePanel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::default())
+                .insert(ConsoleText);
+        });
+    commands.insert_resource(ConsoleFont(asset_server.load("kongtext.ttf")));
+}
+
+fn use_console(
+    mut console: ResMut<Console>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    mut speech_events: EventWriter<SpeechEvent>,
+    mut transcript_events: EventWriter<TranscriptEvent>,
+) {
+    if !console.open {
+        if keys.just_pressed(TOGGLE_KEY) {
+            console.open = true;
+            keys.reset(TOGGLE_KEY);
+        }
+        characters.clear();
+        return;
+    }
+
+    for character in characters.iter() {
+        // the toggle key types a character of its own
+        if !character.char.is_control()
+            && character.char != '`'
+            && console.input.len() < MAX_INPUT_LENGTH
+        {
+            console.input.push(character.char);
+        }
+    }
+    if keys.just_pressed(DELETE_KEY) {
+        console.input.pop();
+    }
+    if keys.just_pressed(OLDER_KEY) {
+        console.older();
+    }
+    if keys.just_pressed(NEWER_KEY) {
+        console.newer();
+    }
+    if keys.just_pressed(COMPLETE_KEY) {
+        if let Some(keyword_matcher) = &keyword_matcher {
+            console.complete(keyword_matcher);
+        }
+    }
+    if keys.just_pressed(TOGGLE_KEY) || keys.just_pressed(CLOSE_KEY) {
+        console.open = false;
+    }
+    if keys.just_pressed(SUBMIT_KEY) {
+        let text = std::mem::take(&mut console.input);
+        console.history_index = None;
+        if !text.trim().is_empty() {
+            if console.history.last() != Some(&text) {
+                console.history.push(text.clone());
+            }
+            if console.history.len() > MAX_HISTORY {
+                console.history.remove(0);
+            }
+            match &keyword_matcher {
+                Some(keyword_matcher) => {
+                    let transcript = Transcript::typed(&text);
+                    run_transcript(
+                        &mut console,
+                        &transcript,
+                        keyword_matcher,
+                        &mut speech_events,
+                    );
+                    transcript_events.send(TranscriptEvent(transcript));
+                }
+                None => console.print("The vocabulary hasn't loaded yet.", WARNING_COLOR),
+            }
+        }
+    }
+
+    // nothing else gets to see the keys while the console is open
+    keys.reset_all();
+}
+
+/// Send the speech events for a transcript, and describe each match, including the ones for
+/// keywords that no puzzle listens for yet.
+fn run_transcript(
+    console: &mut Console,
+    transcript: &Transcript,
+    keyword_matcher: &KeywordMatcher,
+    speech_events: &mut EventWriter<SpeechEvent>,
+) {
+    console.print(format!("> {}", transcript.text), INPUT_COLOR);
+
+    let words: Vec<&str> = transcript.words.iter().map(|w| w.word.as_str()).collect();
+    for keyword_match in keyword_matcher.find_matches(&words) {
+        let matched_words = words[keyword_match.word_index..][..keyword_match.word_count].join(" ");
+        let description = format!(
+            "{:?} match for {:?} on {:?}, similarity {:.2}",
+            keyword_match.kind, keyword_match.keyword, matched_words, keyword_match.similarity
+        );
+        if Keyword::from_name(keyword_match.keyword).is_some() {
+            console.print(format!("  {}", description), INFO_COLOR);
+        } else {
+            console.print(
+                format!("  {}, but no puzzle listens for it", description),
+                WARNING_COLOR,
+            );
+        }
+    }
+
+    let events = keyword_speech_events(transcript, keyword_matcher);
+    if events.is_empty() {
+        console.print("  no speech events", INFO_COLOR);
+    }
+    for mut speech_event in events {
+        speech_event.source = SpeechSource::Typed;
+        console.print(
+            format!(
+                "  SpeechEvent {:?}, confidence {:.2}",
+                speech_event.keyword, speech_event.confidence
+            ),
+            EVENT_COLOR,
+        );
+        info!(
+            "Sending {:?} speech event from the console.",
+            speech_event.keyword
+        );
+        speech_events.send(speech_event);
+    }
+}
+
+fn show_console(
+    console: Res<Console>,
+    font: Res<ConsoleFont>,
+    mut panel_query: Query<&mut Visibility, With<ConsolePanel>>,
+    mut text_query: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    panel_query.single_mut().is_visible = console.open;
+
+    let style = |color| TextStyle {
+        font: font.0.clone(),
+        font_size: 8.0,
+        color,
+    };
+    let mut sections: Vec<TextSection> = console
+        .output
+        .iter()
+        .map(|(line, color)| TextSection::new(format!("{}\n", line), style(*color)))
+        .collect();
+    sections.push(TextSection::new(
+        format!("] {}_", console.input),
+        style(INPUT_COLOR),
+    ));
+    text_query.single_mut().sections = sections;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keywords::Vocabulary;
+
+    fn keyword_matcher() -> KeywordMatcher {
+        let vocabulary: Vocabulary = serde_json::from_str(
+            r#"{
+                "keywords": [
+                    { "keyword": "sugar", "aliases": ["shugar"] },
+                    { "keyword": "mentos", "aliases": ["mentors", "mento"] },
+                    { "keyword": "bridge" },
+                    { "keyword": "café", "aliases": ["cafè"] }
+                ]
+            }"#,
+        )
+        .expect("the vocabulary is valid");
+        KeywordMatcher::new(&vocabulary)
+    }
+
+    fn completed(input: &str) -> Console {
+        let mut console = Console {
+            input: input.to_string(),
+            ..default()
+        };
+        console.complete(&keyword_matcher());
+        console
+    }
+
+    fn output(console: &Console) -> Vec<&str> {
+        console
+            .output
+            .iter()
+            .map(|(line, _)| line.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn words_nothing_starts_with_are_left_alone() {
+        let console = completed("open the chest");
+        assert_eq!(console.input, "open the chest");
+        assert!(console.output.is_empty());
+
+        let console = completed("sugar ");
+        assert_eq!(console.input, "sugar ");
+    }
+
+    #[test]
+    fn one_match_is_completed_in_full() {
+        let console = completed("cross the BRI");
+        assert_eq!(console.input, "cross the bridge ");
+        assert!(console.output.is_empty());
+    }
+
+    #[test]
+    fn several_matches_are_completed_as_far_as_they_agree_and_listed() {
+        let console = completed("me");
+        assert_eq!(console.input, "mento");
+        assert_eq!(output(&console), ["mento  mentors  mentos"]);
+    }
+
+    #[test]
+    fn non_ascii_input_is_completed_by_character() {
+        // a pasted non-breaking space still separates words
+        let console = completed("jam\u{a0}su");
+        assert_eq!(console.input, "jam\u{a0}sugar ");
+
+        // "é" and "è" start with the same byte, which isn't a character of its own
+        let console = completed("ca");
+        assert_eq!(console.input, "caf");
+        assert_eq!(output(&console), ["cafè  café"]);
+
+        let console = completed("CAFÉ");
+        assert_eq!(console.input, "café ");
+    }
+
+    #[test]
+    fn history_is_walked_through_and_back() {
+        let mut console = Console {
+            history: vec!["sugar".to_string(), "mentos".to_string()],
+            ..default()
+        };
+
+        console.newer();
+        assert_eq!(console.input, "");
+        console.older();
+        assert_eq!(console.input, "mentos");
+        console.older();
+        assert_eq!(console.input, "sugar");
+        // the oldest entry is as far back as it goes
+        console.older();
+        assert_eq!(console.input, "sugar");
+        console.newer();
+        assert_eq!(console.input, "mentos");
+        // going past the newest entry leaves an empty line to type on
+        console.newer();
+        assert_eq!(console.input, "");
+        assert_eq!(console.history_index, None);
+    }
+
+    #[test]
+    fn empty_history_does_nothing() {
+        let mut console = Console::default();
+        console.older();
+        console.newer();
+        assert_eq!(console.input, "");
+        assert_eq!(console.history_index, None);
+    }
+}
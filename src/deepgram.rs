@@ -0,0 +1,532 @@
+//! A typed model of the JSON messages Deepgram sends back on a streaming `/v1/listen` connection.
+//! See: https://developers.deepgram.com/reference/streaming
+use serde::Deserialize;
+
+/// Every text message we receive on the websocket is one of these. Deepgram tags each message with
+/// a `type` field, except for some older error payloads which we handle separately in `parse`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum StreamingMessage {
+    Results(Results),
+    Metadata(Metadata),
+    UtteranceEnd(UtteranceEnd),
+    SpeechStarted(SpeechStarted),
+    Error(StreamingError),
+    /// Any message type we don't know about yet. We'd rather ignore these than fail to parse.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A transcript for some span of the audio stream.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Results {
+    #[serde(default)]
+    pub channel_index: Vec<u32>,
+    /// Length of the transcribed span, in seconds.
+    #[serde(default)]
+    pub duration: f32,
+    /// Offset of the transcribed span from the start of the stream, in seconds.
+    #[serde(default)]
+    pub start: f32,
+    /// Whether Deepgram will send any more revisions of this span.
+    #[serde(default)]
+    pub is_final: bool,
+    /// Whether Deepgram detected the end of an utterance at the end of this span.
+    #[serde(default)]
+    pub speech_final: bool,
+    pub channel: Channel,
+}
+
+impl Results {
+    /// The most likely transcription of this span, if Deepgram gave us one.
+    pub fn best_alternative(&self) -> Option<&Alternative> {
+        self.channel.alternatives.first()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Channel {
+    #[serde(default)]
+    pub alternatives: Vec<Alternative>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Alternative {
+    #[serde(default)]
+    pub transcript: String,
+    #[serde(default)]
+    pub confidence: f32,
+    #[serde(default)]
+    pub words: Vec<Word>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Word {
+    /// The recognized word, lowercase and without punctuation.
+    pub word: String,
+    pub start: f32,
+    pub end: f32,
+    #[serde(default)]
+    pub confidence: f32,
+    /// The word as it appears in the transcript when `smart_format` or `punctuate` are enabled.
+    #[serde(default)]
+    pub punctuated_word: Option<String>,
+}
+
+/// Sent once per connection, describing the request and the model that is serving it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Metadata {
+    #[serde(default)]
+    pub request_id: String,
+    #[serde(default)]
+    pub duration: f32,
+    #[serde(default)]
+    pub channels: u32,
+}
+
+/// Sent when Deepgram detects a gap after the last finalized word, if `utterance_end_ms` is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct UtteranceEnd {
+    #[serde(default)]
+    pub channel: Vec<u32>,
+    #[serde(default)]
+    pub last_word_end: f32,
+}
+
+/// Sent when Deepgram detects the start of speech, if `vad_events` is set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct SpeechStarted {
+    #[serde(default)]
+    pub channel: Vec<u32>,
+    #[serde(default)]
+    pub timestamp: f32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct StreamingError {
+    #[serde(default)]
+    pub variant: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Older Deepgram errors are sent without a `type` field, so we can't rely on the tag alone.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMessage {
+    Tagged(StreamingMessage),
+    UntaggedError(UntaggedError),
+}
+
+#[derive(Deserialize)]
+struct UntaggedError {
+    err_code: String,
+    #[serde(default)]
+    err_msg: String,
+}
+
+/// Parse a text message received on the listen websocket.
+pub fn parse(message: &str) -> Result<StreamingMessage, serde_json::Error> {
+    Ok(match serde_json::from_str(message)? {
+        RawMessage::Tagged(message) => message,
+        RawMessage::UntaggedError(error) => StreamingMessage::Error(StreamingError {
+            variant: error.err_code,
+            description: error.err_msg,
+            message: String::new(),
+        }),
+    })
+}
+
+/// Text messages we can send Deepgram alongside the audio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Keeps the stream open while we have no audio to send. Deepgram closes streams that go
+    /// about ten seconds without either.
+    KeepAlive,
+    /// Asks Deepgram to finish transcribing what it has, send the final results and then close
+    /// the connection.
+    CloseStream,
+}
+
+impl ControlMessage {
+    pub fn to_json(self) -> &'static str {
+        match self {
+            ControlMessage::KeepAlive => r#"{"type":"KeepAlive"}"#,
+            ControlMessage::CloseStream => r#"{"type":"CloseStream"}"#,
+        }
+    }
+}
+
+/// Where streaming transcription requests go unless told otherwise.
+pub const DEFAULT_LISTEN_URL: &str = "wss://api.deepgram.com/v1/listen";
+
+/// Query parameters for a streaming `/v1/listen` connection. Anything left unset is left out of
+/// the URL so that Deepgram's defaults apply.
+/// See: https://developers.deepgram.com/reference/streaming
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListenOptions {
+    base_url: String,
+    sample_rate: u32,
+    channels: u16,
+    model: Option<String>,
+    language: Option<String>,
+    endpointing: Option<String>,
+    smart_format: Option<bool>,
+    interim_results: Option<bool>,
+    utterance_end_ms: Option<u32>,
+    keywords: Vec<(String, f32)>,
+}
+
+impl Default for ListenOptions {
+    fn default() -> Self {
+        ListenOptions {
+            base_url: DEFAULT_LISTEN_URL.to_string(),
+            sample_rate: 44_100,
+            channels: 1,
+            model: None,
+            language: None,
+            endpointing: None,
+            smart_format: None,
+            interim_results: None,
+            utterance_end_ms: None,
+            keywords: Vec::new(),
+        }
+    }
+}
+
+impl ListenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect somewhere other than Deepgram's hosted API, such as a proxy or a self-hosted
+    /// deployment.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// The sample rate of the linear16 audio we will send.
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    /// How many milliseconds of silence end an utterance, or `None` to turn endpointing off.
+    pub fn endpointing(mut self, milliseconds: Option<u32>) -> Self {
+        self.endpointing =
+            Some(milliseconds.map_or_else(|| "false".to_string(), |ms| ms.to_string()));
+        self
+    }
+
+    pub fn smart_format(mut self, smart_format: bool) -> Self {
+        self.smart_format = Some(smart_format);
+        self
+    }
+
+    pub fn interim_results(mut self, interim_results: bool) -> Self {
+        self.interim_results = Some(interim_results);
+        self
+    }
+
+    /// Ask for an `UtteranceEnd` message after this many milliseconds without a new word. Deepgram
+    /// only honours this when interim results are on.
+    pub fn utterance_end_ms(mut self, milliseconds: u32) -> Self {
+        self.utterance_end_ms = Some(milliseconds);
+        self
+    }
+
+    /// Make the recognizer more likely to hear each keyword by its boost. Boosts above 10 or so
+    /// tend to make it hear keywords where they weren't said.
+    pub fn keywords<K: Into<String>>(
+        mut self,
+        keywords: impl IntoIterator<Item = (K, f32)>,
+    ) -> Self {
+        self.keywords.extend(
+            keywords
+                .into_iter()
+                .map(|(keyword, boost)| (keyword.into(), boost)),
+        );
+        self
+    }
+
+    /// The full websocket URL, query string included.
+    pub fn url(&self) -> String {
+        let mut url = format!(
+            "{}?encoding=linear16&sample_rate={}&channels={}",
+            self.base_url, self.sample_rate, self.channels
+        );
+
+        let mut push = |name: &str, value: &str| {
+            url.push('&');
+            url.push_str(name);
+            url.push('=');
+            url.push_str(&percent_encode(value));
+        };
+        if let Some(model) = &self.model {
+            push("model", model);
+        }
+        if let Some(language) = &self.language {
+            push("language", language);
+        }
+        if let Some(endpointing) = &self.endpointing {
+            push("endpointing", endpointing);
+        }
+        if let Some(smart_format) = self.smart_format {
+            push("smart_format", &smart_format.to_string());
+        }
+        if let Some(interim_results) = self.interim_results {
+            push("interim_results", &interim_results.to_string());
+        }
+        if let Some(utterance_end_ms) = self.utterance_end_ms {
+            push("utterance_end_ms", &utterance_end_ms.to_string());
+        }
+        for (keyword, boost) in &self.keywords {
+            url.push_str(&format!("&keywords={}:{}", percent_encode(keyword), boost));
+        }
+
+        url
+    }
+}
+
+/// Percent-encode everything but the characters RFC 3986 leaves unreserved.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Messages as Deepgram sends them, trimmed of fields we don't read.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    pub const INTERIM_RESULTS: &str = r#"{
+        "type": "Results",
+        "channel_index": [0, 1],
+        "duration": 1.02,
+        "start": 0.0,
+        "is_final": false,
+        "speech_final": false,
+        "channel": {
+            "alternatives": [{
+                "transcript": "sugar",
+                "confidence": 0.87,
+                "words": [
+                    {"word": "sugar", "start": 0.24, "end": 0.72, "confidence": 0.87}
+                ]
+            }]
+        },
+        "metadata": {"request_id": "b5e3c1a2", "model_uuid": "4d892fb6"},
+        "from_finalize": false
+    }"#;
+
+    pub const FINAL_RESULTS: &str = r#"{
+        "type": "Results",
+        "channel_index": [0, 1],
+        "duration": 1.5,
+        "start": 0.0,
+        "is_final": true,
+        "speech_final": true,
+        "channel": {
+            "alternatives": [{
+                "transcript": "Sugar, please.",
+                "confidence": 0.98,
+                "words": [
+                    {
+                        "word": "sugar",
+                        "start": 0.24,
+                        "end": 0.72,
+                        "confidence": 0.99,
+                        "punctuated_word": "Sugar,"
+                    },
+                    {
+                        "word": "please",
+                        "start": 0.8,
+                        "end": 1.2,
+                        "confidence": 0.97,
+                        "punctuated_word": "please."
+                    }
+                ]
+            }]
+        }
+    }"#;
+
+    pub const METADATA: &str = r#"{
+        "type": "Metadata",
+        "transaction_key": "deprecated",
+        "request_id": "b5e3c1a2",
+        "sha256": "1dbb8e1a",
+        "created": "2024-05-01T12:00:00.000Z",
+        "duration": 3.2,
+        "channels": 1,
+        "models": ["4d892fb6"]
+    }"#;
+
+    pub const UTTERANCE_END: &str =
+        r#"{"type": "UtteranceEnd", "channel": [0, 1], "last_word_end": 2.395}"#;
+
+    pub const SPEECH_STARTED: &str =
+        r#"{"type": "SpeechStarted", "channel": [0], "timestamp": 1.54}"#;
+
+    pub const ERROR: &str = r#"{
+        "type": "Error",
+        "variant": "DATA-0000",
+        "description": "Failed to decode audio.",
+        "message": "Make sure the audio is linear16."
+    }"#;
+
+    pub const UNTAGGED_ERROR: &str = r#"{
+        "err_code": "INVALID_AUTH",
+        "err_msg": "Invalid credentials.",
+        "request_id": "b5e3c1a2"
+    }"#;
+
+    pub const UNKNOWN: &str = r#"{"type": "Finalized", "channel": [0]}"#;
+
+    pub const MALFORMED: &str = r#"{"type": "Results", "channel": "#;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::*;
+    use super::*;
+
+    #[test]
+    fn interim_results() {
+        let results = match parse(INTERIM_RESULTS) {
+            Ok(StreamingMessage::Results(results)) => results,
+            other => panic!("expected results, got {:?}", other),
+        };
+        assert!(!results.is_final);
+        assert!(!results.speech_final);
+        let alternative = results.best_alternative().expect("an alternative");
+        assert_eq!(alternative.transcript, "sugar");
+        assert_eq!(alternative.words.len(), 1);
+        assert_eq!(alternative.words[0].word, "sugar");
+        assert_eq!(alternative.words[0].punctuated_word, None);
+    }
+
+    #[test]
+    fn final_results() {
+        let results = match parse(FINAL_RESULTS) {
+            Ok(StreamingMessage::Results(results)) => results,
+            other => panic!("expected results, got {:?}", other),
+        };
+        assert!(results.is_final);
+        assert!(results.speech_final);
+        assert_eq!(results.duration, 1.5);
+        let alternative = results.best_alternative().expect("an alternative");
+        assert_eq!(alternative.transcript, "Sugar, please.");
+        let words: Vec<&str> = alternative.words.iter().map(|w| w.word.as_str()).collect();
+        assert_eq!(words, ["sugar", "please"]);
+        assert_eq!(
+            alternative.words[0].punctuated_word.as_deref(),
+            Some("Sugar,")
+        );
+        assert_eq!(alternative.words[1].start, 0.8);
+    }
+
+    #[test]
+    fn results_without_alternatives() {
+        let message = r#"{"type": "Results", "is_final": true, "channel": {"alternatives": []}}"#;
+        match parse(message) {
+            Ok(StreamingMessage::Results(results)) => {
+                assert!(results.best_alternative().is_none())
+            }
+            other => panic!("expected results, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        assert_eq!(
+            parse(METADATA).ok(),
+            Some(StreamingMessage::Metadata(Metadata {
+                request_id: "b5e3c1a2".to_string(),
+                duration: 3.2,
+                channels: 1,
+            }))
+        );
+    }
+
+    #[test]
+    fn utterance_end() {
+        assert_eq!(
+            parse(UTTERANCE_END).ok(),
+            Some(StreamingMessage::UtteranceEnd(UtteranceEnd {
+                channel: vec![0, 1],
+                last_word_end: 2.395,
+            }))
+        );
+    }
+
+    #[test]
+    fn speech_started() {
+        assert_eq!(
+            parse(SPEECH_STARTED).ok(),
+            Some(StreamingMessage::SpeechStarted(SpeechStarted {
+                channel: vec![0],
+                timestamp: 1.54,
+            }))
+        );
+    }
+
+    #[test]
+    fn error() {
+        assert_eq!(
+            parse(ERROR).ok(),
+            Some(StreamingMessage::Error(StreamingError {
+                variant: "DATA-0000".to_string(),
+                description: "Failed to decode audio.".to_string(),
+                message: "Make sure the audio is linear16.".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn untagged_error() {
+        assert_eq!(
+            parse(UNTAGGED_ERROR).ok(),
+            Some(StreamingMessage::Error(StreamingError {
+                variant: "INVALID_AUTH".to_string(),
+                description: "Invalid credentials.".to_string(),
+                message: String::new(),
+            }))
+        );
+    }
+
+    #[test]
+    fn unknown_type() {
+        assert_eq!(parse(UNKNOWN).ok(), Some(StreamingMessage::Unknown));
+    }
+
+    #[test]
+    fn malformed_message() {
+        assert!(parse(MALFORMED).is_err());
+        assert!(parse("").is_err());
+        assert!(parse(r#"{"no_type": true}"#).is_err());
+    }
+}
@@ -0,0 +1,467 @@
+//! A stand-in for Deepgram's streaming `/v1/listen` endpoint, for trying the game's speech
+//! handling without an API key or a network connection.
+//!
+//! The mock checks the request the way Deepgram would: a `Token` API key or `Bearer` access token
+//! (as an `Authorization` header or a `token` or `bearer` websocket subprotocol) and a linear16
+//! query string. Then it answers the audio with scripted `Results` messages. Each line of the
+//! script is a transcript, optionally prefixed with `@SECONDS` to send it once that much audio has
+//! arrived. Lines without a time are sent, in order, as each burst of loud audio ends, or as the
+//! stream is closed partway through one.
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! @1.5 sugar
+//! open the treasure chest
+//! ```
+//!
+//! Without a script, every burst is heard as "sugar".
+//!
+//! The `deepgram-mock` binary runs it on its own, and tests start it on a free port.
+use std::error::Error;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use bevy::log::{info, warn};
+use serde_json::{json, Value};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::{Message, WebSocket};
+
+/// The only path the mock answers on, as Deepgram's streaming endpoint.
+pub const LISTEN_PATH: &str = "/v1/listen";
+
+/// What each burst is heard as when there's no script.
+const DEFAULT_TRANSCRIPT: &str = "sugar";
+
+/// Loudness is measured over frames this long.
+const FRAME_SECONDS: f32 = 0.01;
+/// RMS level, as a fraction of full scale, above which a frame counts as speech.
+const SPEECH_THRESHOLD: f32 = 0.02;
+/// How long the audio has to stay quiet before a burst is over.
+const BURST_END_SILENCE_SECONDS: f32 = 0.3;
+/// Bursts shorter than this are clicks and bumps rather than words.
+const MIN_BURST_SECONDS: f32 = 0.1;
+/// How long each word of a timed script line is said to last.
+const SCRIPTED_WORD_SECONDS: f32 = 0.3;
+const CONFIDENCE: f32 = 0.99;
+
+/// What the mock hears in the audio, in the format described above.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    /// Sorted by time.
+    timed: Vec<(f32, String)>,
+    on_burst: Vec<String>,
+}
+
+impl Script {
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut parsed = Script::default();
+        for line in script.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('@') {
+                Some(timed) => {
+                    let (time, transcript) =
+                        timed.split_once(char::is_whitespace).unwrap_or((timed, ""));
+                    let time = time
+                        .parse::<f32>()
+                        .map_err(|_| format!("bad time in script line {:?}", line))?;
+                    parsed.timed.push((time, transcript.trim().to_string()));
+                }
+                None => parsed.on_burst.push(line.to_string()),
+            }
+        }
+        parsed.timed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(parsed)
+    }
+}
+
+/// The mock server. Bind it to port 0 for a free port, and ask it which one it got.
+pub struct MockServer {
+    listener: TcpListener,
+    api_key: Option<String>,
+    script: Option<Script>,
+}
+
+impl MockServer {
+    /// Listen on `address`. Clients must present `api_key`, or any key if it's `None`, and hear
+    /// what `script` says, or "sugar" for every burst without one.
+    pub fn bind(
+        address: &str,
+        api_key: Option<String>,
+        script: Option<Script>,
+    ) -> std::io::Result<Self> {
+        Ok(MockServer {
+            listener: TcpListener::bind(address)?,
+            api_key,
+            script,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve each connection on its own thread, forever.
+    pub fn run(self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!("Could not accept a connection: {}", error);
+                    continue;
+                }
+            };
+
+            let api_key = self.api_key.clone();
+            let script = self.script.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "unknown".to_string(), |address| address.to_string());
+                match serve_connection(stream, api_key.as_deref(), script) {
+                    Ok(()) => info!("{} disconnected.", peer),
+                    Err(error) => warn!("{} disconnected: {}", peer, error),
+                }
+            });
+        }
+    }
+}
+
+/// What the client asked for in the query string.
+struct ListenRequest {
+    sample_rate: u32,
+    interim_results: bool,
+    /// The subprotocol that carried the credential, which has to be echoed back.
+    protocol: Option<&'static str>,
+}
+
+// tungstenite's handshake callback has to return its large `ErrorResponse`
+#[allow(clippy::result_large_err)]
+fn check_request(request: &Request, api_key: Option<&str>) -> Result<ListenRequest, ErrorResponse> {
+    let reject = |status: StatusCode, message: &str| {
+        let mut error = ErrorResponse::new(Some(message.to_string()));
+        *error.status_mut() = status;
+        error
+    };
+
+    if request.uri().path() != LISTEN_PATH {
+        return Err(reject(StatusCode::NOT_FOUND, "not found"));
+    }
+
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let protocols: Vec<&str> = header("Sec-WebSocket-Protocol")
+        .map(|protocols| protocols.split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    // there's nothing to grant access tokens here, so the API key doubles as one
+    let (token, protocol) = match (header("Authorization"), protocols.as_slice()) {
+        (Some(authorization), _) => (
+            authorization
+                .strip_prefix("Token ")
+                .or_else(|| authorization.strip_prefix("Bearer ")),
+            None,
+        ),
+        (None, ["token", key]) => (Some(*key), Some("token")),
+        (None, ["bearer", token]) => (Some(*token), Some("bearer")),
+        _ => (None, None),
+    };
+    match (token, api_key) {
+        (None, _) => {
+            return Err(reject(
+                StatusCode::UNAUTHORIZED,
+                "missing Token or Bearer credential",
+            ))
+        }
+        (Some(token), Some(api_key)) if token != api_key => {
+            return Err(reject(StatusCode::UNAUTHORIZED, "wrong API key"))
+        }
+        _ => {}
+    }
+
+    let query = request.uri().query().unwrap_or("");
+    let parameter = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    };
+    if parameter("encoding") != Some("linear16") {
+        return Err(reject(StatusCode::BAD_REQUEST, "encoding must be linear16"));
+    }
+    if parameter("channels").unwrap_or("1") != "1" {
+        return Err(reject(
+            StatusCode::BAD_REQUEST,
+            "only mono audio is supported",
+        ));
+    }
+    let sample_rate = parameter("sample_rate")
+        .and_then(|sample_rate| sample_rate.parse().ok())
+        .filter(|sample_rate| *sample_rate > 0)
+        .ok_or_else(|| reject(StatusCode::BAD_REQUEST, "sample_rate is required"))?;
+
+    Ok(ListenRequest {
+        sample_rate,
+        interim_results: parameter("interim_results") == Some("true"),
+        protocol,
+    })
+}
+
+// tungstenite's handshake callback has to return its large `ErrorResponse`
+#[allow(clippy::result_large_err)]
+fn serve_connection(
+    stream: TcpStream,
+    api_key: Option<&str>,
+    script: Option<Script>,
+) -> Result<(), Box<dyn Error>> {
+    let mut listen_request = None;
+    let mut socket =
+        tungstenite::accept_hdr(stream, |request: &Request, mut response: Response| {
+            let checked = check_request(request, api_key)?;
+            // browsers drop the connection unless the server picks one of their subprotocols
+            if let Some(protocol) = checked.protocol {
+                response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            }
+            listen_request = Some(checked);
+            Ok(response)
+        })
+        // the handshake error holds on to the callback, and with it `listen_request`
+        .map_err(|error| error.to_string())?;
+    let listen_request = listen_request.ok_or("handshake finished without a request")?;
+
+    let mut transcriber = Transcriber::new(listen_request, script);
+    loop {
+        match socket.read_message() {
+            Ok(Message::Binary(audio)) => {
+                for message in transcriber.push_audio(&audio) {
+                    socket.write_message(Message::Text(message.to_string()))?;
+                }
+            }
+            Ok(Message::Text(text)) => {
+                let control: Value = serde_json::from_str(&text)?;
+                match control["type"].as_str() {
+                    Some("KeepAlive") => {}
+                    Some("CloseStream") => return finish(&mut socket, &mut transcriber),
+                    _ => return Err(format!("unexpected message {}", text).into()),
+                }
+            }
+            // tungstenite has queued the reply to the client's close, which this sends
+            Ok(Message::Close(_)) => {
+                return match socket.close(None) {
+                    Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(()),
+                    Err(error) => Err(error.into()),
+                }
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Finish off the burst being heard, if any, then send the closing metadata and hang up, as
+/// Deepgram does when asked to close the stream.
+fn finish(
+    socket: &mut WebSocket<TcpStream>,
+    transcriber: &mut Transcriber,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(message) = transcriber.finish() {
+        socket.write_message(Message::Text(message.to_string()))?;
+    }
+    let metadata = json!({
+        "type": "Metadata",
+        "request_id": "mock",
+        "duration": transcriber.seconds(),
+        "channels": 1,
+    });
+    socket.write_message(Message::Text(metadata.to_string()))?;
+    let _ = socket.close(Some(CloseFrame {
+        code: CloseCode::Normal,
+        reason: "".into(),
+    }));
+    while socket.read_message().is_ok() {}
+    Ok(())
+}
+
+/// Turns the audio stream into scripted results.
+struct Transcriber {
+    request: ListenRequest,
+    script: Script,
+    next_timed: usize,
+    next_on_burst: usize,
+    /// Whether every burst gets the same transcript, rather than the next line of the script.
+    repeat_on_burst: bool,
+    samples_received: u64,
+    /// Samples of the frame being measured, and the sum of their squares.
+    frame: (u32, f64),
+    burst: Option<Burst>,
+}
+
+struct Burst {
+    start: f32,
+    last_loud: f32,
+    sent_interim: bool,
+}
+
+impl Transcriber {
+    fn new(request: ListenRequest, script: Option<Script>) -> Self {
+        let repeat_on_burst = script.is_none();
+        let script = script.unwrap_or_else(|| Script {
+            timed: Vec::new(),
+            on_burst: vec![DEFAULT_TRANSCRIPT.to_string()],
+        });
+        Transcriber {
+            request,
+            script,
+            next_timed: 0,
+            next_on_burst: 0,
+            repeat_on_burst,
+            samples_received: 0,
+            frame: (0, 0.0),
+            burst: None,
+        }
+    }
+
+    fn seconds(&self) -> f32 {
+        self.samples_received as f32 / self.request.sample_rate as f32
+    }
+
+    fn push_audio(&mut self, audio: &[u8]) -> Vec<Value> {
+        let frame_samples = (self.request.sample_rate as f32 * FRAME_SECONDS).max(1.0) as u32;
+        let mut messages = Vec::new();
+
+        for sample in audio.chunks_exact(2) {
+            let sample = i16::from_le_bytes([sample[0], sample[1]]) as f64 / i16::MAX as f64;
+            self.samples_received += 1;
+            self.frame.0 += 1;
+            self.frame.1 += sample * sample;
+            if self.frame.0 < frame_samples {
+                continue;
+            }
+
+            let rms = (self.frame.1 / self.frame.0 as f64).sqrt() as f32;
+            self.frame = (0, 0.0);
+            self.end_of_frame(rms >= SPEECH_THRESHOLD, &mut messages);
+        }
+
+        messages
+    }
+
+    /// The final result for the burst being heard when the stream ends, if it's long enough to be
+    /// a word.
+    fn finish(&mut self) -> Option<Value> {
+        let burst = self.burst.take()?;
+        if burst.last_loud - burst.start < MIN_BURST_SECONDS {
+            return None;
+        }
+        let transcript = self.script.on_burst.get(self.next_on_burst)?;
+        let message = results(transcript, burst.start, burst.last_loud, true);
+        if !self.repeat_on_burst {
+            self.next_on_burst += 1;
+        }
+        Some(message)
+    }
+
+    fn end_of_frame(&mut self, loud: bool, messages: &mut Vec<Value>) {
+        let now = self.seconds();
+
+        while let Some((time, transcript)) = self.script.timed.get(self.next_timed) {
+            if *time > now {
+                break;
+            }
+            let end = time + SCRIPTED_WORD_SECONDS * transcript.split_whitespace().count() as f32;
+            if self.request.interim_results {
+                messages.push(results(transcript, *time, end, false));
+            }
+            messages.push(results(transcript, *time, end, true));
+            self.next_timed += 1;
+        }
+
+        match &mut self.burst {
+            None if loud => {
+                self.burst = Some(Burst {
+                    start: now - FRAME_SECONDS,
+                    last_loud: now,
+                    sent_interim: false,
+                })
+            }
+            None => {}
+            Some(burst) if loud => {
+                burst.last_loud = now;
+                // an interim result partway through, like Deepgram sends while a word is spoken
+                if self.request.interim_results
+                    && !burst.sent_interim
+                    && now - burst.start >= MIN_BURST_SECONDS
+                {
+                    if let Some(transcript) = self.script.on_burst.get(self.next_on_burst) {
+                        messages.push(results(transcript, burst.start, now, false));
+                    }
+                    burst.sent_interim = true;
+                }
+            }
+            Some(burst) if now - burst.last_loud < BURST_END_SILENCE_SECONDS => {}
+            Some(burst) => {
+                if burst.last_loud - burst.start >= MIN_BURST_SECONDS {
+                    if let Some(transcript) = self.script.on_burst.get(self.next_on_burst) {
+                        messages.push(results(transcript, burst.start, burst.last_loud, true));
+                        if !self.repeat_on_burst {
+                            self.next_on_burst += 1;
+                        }
+                    }
+                }
+                self.burst = None;
+            }
+        }
+    }
+}
+
+/// A `Results` message saying `transcript` between `start` and `end`, with its words spread evenly
+/// across the span.
+fn results(transcript: &str, start: f32, end: f32, is_final: bool) -> Value {
+    let punctuated: Vec<&str> = transcript.split_whitespace().collect();
+    let word_seconds = (end - start) / punctuated.len().max(1) as f32;
+    let words: Vec<Value> = punctuated
+        .iter()
+        .enumerate()
+        .map(|(index, punctuated_word)| {
+            let word: String = punctuated_word
+                .chars()
+                .filter(|c| c.is_alphanumeric() || *c == '\'')
+                .flat_map(char::to_lowercase)
+                .collect();
+            json!({
+                "word": word,
+                "start": start + word_seconds * index as f32,
+                "end": start + word_seconds * (index + 1) as f32,
+                "confidence": CONFIDENCE,
+                "punctuated_word": punctuated_word,
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "Results",
+        "channel_index": [0, 1],
+        "duration": end - start,
+        "start": start,
+        "is_final": is_final,
+        "speech_final": is_final,
+        "channel": {
+            "alternatives": [{
+                "transcript": transcript,
+                "confidence": CONFIDENCE,
+                "words": words,
+            }],
+        },
+    })
+}
@@ -0,0 +1,362 @@
+//! A small websocket proxy that holds the Deepgram API key, so that the game doesn't have to.
+//!
+//! The game connects to the proxy, and the proxy opens the same `/v1/listen` request upstream
+//! with the key attached, then passes messages back and forth until either side hangs up.
+//!
+//! Since anything that can reach the proxy can spend the key, it only lets in:
+//!
+//! - browsers on pages served from this machine, or from an origin it's been told to allow.
+//!   Clients that aren't browsers don't send an `Origin`, and are let in regardless.
+//! - clients presenting the proxy's shared secret, if it has one, in the same way they'd present
+//!   a Deepgram API key: as a `Token` or `Bearer` credential, in an `Authorization` header or a
+//!   `token` or `bearer` websocket subprotocol.
+//!
+//! Only query parameters for streaming transcription are forwarded.
+//!
+//! The `deepgram-proxy` binary runs it on its own, and tests start it on a free port.
+use std::error::Error;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use bevy::log::{info, warn};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+pub const DEFAULT_UPSTREAM: &str = "wss://api.deepgram.com";
+
+/// Only streaming transcription is forwarded, so the proxy can't be used to spend the key on the
+/// rest of the API.
+pub const FORWARDED_PATH: &str = "/v1/listen";
+
+/// The query parameters that are passed on to Deepgram. Anything else is dropped, e.g. `callback`,
+/// which would have Deepgram send the transcripts somewhere else too.
+const FORWARDED_PARAMETERS: &[&str] = &[
+    "channels",
+    "diarize",
+    "encoding",
+    "endpointing",
+    "filler_words",
+    "interim_results",
+    "keywords",
+    "language",
+    "model",
+    "numerals",
+    "profanity_filter",
+    "punctuate",
+    "sample_rate",
+    "smart_format",
+    "tier",
+    "utterance_end_ms",
+    "vad_events",
+    "version",
+];
+
+/// How long to wait for a message from one side before checking the other. This bounds the
+/// latency the proxy adds.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where the proxy forwards to, and who it lets in.
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    /// Where to forward connections to, without a trailing slash.
+    pub upstream: String,
+    /// The Deepgram API key to attach to each forwarded connection.
+    pub api_key: String,
+    /// What clients have to present to be let in, or `None` to let in any client that passes the
+    /// origin check.
+    pub secret: Option<String>,
+    /// Browser origins to let in besides those on this machine, e.g. `https://example.com`.
+    pub allowed_origins: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Forward to `upstream` with `api_key`, letting in any client on this machine.
+    pub fn new(upstream: &str, api_key: &str) -> Self {
+        ProxyConfig {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            secret: None,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+/// The proxy server. Bind it to port 0 for a free port, and ask it which one it got.
+pub struct ProxyServer {
+    listener: TcpListener,
+    config: ProxyConfig,
+}
+
+impl ProxyServer {
+    pub fn bind(address: &str, config: ProxyConfig) -> io::Result<Self> {
+        Ok(ProxyServer {
+            listener: TcpListener::bind(address)?,
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Forward each connection on its own thread, forever.
+    pub fn run(self) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!("Could not accept a connection: {}", error);
+                    continue;
+                }
+            };
+
+            let config = self.config.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map_or_else(|_| "unknown".to_string(), |address| address.to_string());
+                match proxy_connection(stream, &config) {
+                    Ok(()) => info!("{} disconnected.", peer),
+                    Err(error) => warn!("{} disconnected: {}", peer, error),
+                }
+            });
+        }
+    }
+}
+
+/// What to forward for a request the proxy has let in.
+struct ForwardedRequest {
+    /// The path and filtered query string to open upstream.
+    path: String,
+    /// The subprotocol the client offered its credential in, which has to be echoed back.
+    protocol: Option<&'static str>,
+}
+
+// tungstenite's handshake callback has to return its large `ErrorResponse`
+#[allow(clippy::result_large_err)]
+fn check_request(
+    request: &Request,
+    config: &ProxyConfig,
+) -> Result<ForwardedRequest, ErrorResponse> {
+    let reject = |status: StatusCode, message: &str| {
+        let mut error = ErrorResponse::new(Some(message.to_string()));
+        *error.status_mut() = status;
+        error
+    };
+
+    if request.uri().path() != FORWARDED_PATH {
+        return Err(reject(
+            StatusCode::NOT_FOUND,
+            &format!("only {} is forwarded", FORWARDED_PATH),
+        ));
+    }
+
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    if let Some(origin) = header("Origin") {
+        if !is_allowed_origin(origin, &config.allowed_origins) {
+            return Err(reject(StatusCode::FORBIDDEN, "origin not allowed"));
+        }
+    }
+
+    let protocols: Vec<&str> = header("Sec-WebSocket-Protocol")
+        .map(|protocols| protocols.split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    let (credential, protocol) = match (header("Authorization"), protocols.as_slice()) {
+        (Some(authorization), _) => (
+            authorization
+                .strip_prefix("Token ")
+                .or_else(|| authorization.strip_prefix("Bearer ")),
+            None,
+        ),
+        (None, ["token", credential]) => (Some(*credential), Some("token")),
+        (None, ["bearer", credential]) => (Some(*credential), Some("bearer")),
+        _ => (None, None),
+    };
+    if let Some(secret) = &config.secret {
+        if credential != Some(secret.as_str()) {
+            return Err(reject(StatusCode::UNAUTHORIZED, "wrong or missing secret"));
+        }
+    }
+
+    let query = filter_query(request.uri().query().unwrap_or(""));
+    let path = if query.is_empty() {
+        FORWARDED_PATH.to_string()
+    } else {
+        format!("{}?{}", FORWARDED_PATH, query)
+    };
+    Ok(ForwardedRequest { path, protocol })
+}
+
+/// Pages served from this machine are let in, on any port, as well as the `allowed` origins.
+fn is_allowed_origin(origin: &str, allowed: &[String]) -> bool {
+    if allowed.iter().any(|allowed| allowed == origin) {
+        return true;
+    }
+    let host_and_port = match origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+    {
+        Some(host_and_port) => host_and_port,
+        None => return false,
+    };
+    let host = match host_and_port.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next(),
+        None => host_and_port.split(':').next(),
+    };
+    matches!(host, Some("localhost" | "127.0.0.1" | "::1"))
+}
+
+/// Keep only the `FORWARDED_PARAMETERS`, in their original order.
+fn filter_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            FORWARDED_PARAMETERS.contains(&name)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// tungstenite's handshake callback has to return its large `ErrorResponse`
+#[allow(clippy::result_large_err)]
+fn proxy_connection(stream: TcpStream, config: &ProxyConfig) -> Result<(), Box<dyn Error>> {
+    let mut path = String::new();
+    let mut client =
+        tungstenite::accept_hdr(stream, |request: &Request, mut response: Response| {
+            let forwarded = check_request(request, config)?;
+            // browsers drop the connection unless the server picks one of their subprotocols
+            if let Some(protocol) = forwarded.protocol {
+                response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            }
+            path = forwarded.path;
+            Ok(response)
+        })
+        // the handshake error holds on to the callback, and with it `path`
+        .map_err(|error| error.to_string())?;
+
+    let mut request = format!("{}{}", config.upstream, path).into_client_request()?;
+    request.headers_mut().insert(
+        "Authorization",
+        format!("Token {}", config.api_key).parse()?,
+    );
+    let (mut upstream, _) = tungstenite::connect(request)?;
+
+    set_read_timeout(client.get_ref())?;
+    match upstream.get_ref() {
+        MaybeTlsStream::Plain(stream) => set_read_timeout(stream)?,
+        MaybeTlsStream::Rustls(stream) => set_read_timeout(stream.get_ref())?,
+        _ => {}
+    }
+
+    loop {
+        let client_open = forward(&mut client, &mut upstream)?;
+        let upstream_open = forward(&mut upstream, &mut client)?;
+        if !client_open || !upstream_open {
+            return Ok(());
+        }
+    }
+}
+
+fn set_read_timeout(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))
+}
+
+/// Pass on at most one message from `from` to `to`. Returns whether `from` is still open, closing
+/// `to` when it isn't.
+fn forward<S, T>(from: &mut WebSocket<S>, to: &mut WebSocket<T>) -> Result<bool, Box<dyn Error>>
+where
+    S: io::Read + io::Write,
+    T: io::Read + io::Write,
+{
+    match from.read_message() {
+        // tungstenite answers pings itself
+        Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => Ok(true),
+        // `to` may already be closing, in which case it's fine that it can't start to
+        Ok(Message::Close(frame)) => {
+            let _ = to.close(frame);
+            let _ = to.write_pending();
+            Ok(true)
+        }
+        Ok(message) => {
+            to.write_message(message)?;
+            Ok(true)
+        }
+        Err(tungstenite::Error::Io(error))
+            if matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(true)
+        }
+        Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+            let _ = to.close(None);
+            let _ = to.write_pending();
+            Ok(false)
+        }
+        Err(error) => {
+            let _ = to.close(None);
+            let _ = to.write_pending();
+            Err(error.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_origins_are_allowed() {
+        for origin in [
+            "http://localhost:1334",
+            "http://127.0.0.1:1334",
+            "https://localhost",
+            "http://[::1]:8080",
+        ] {
+            assert!(is_allowed_origin(origin, &[]), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn other_origins_have_to_be_allowed() {
+        let allowed = ["https://jamfest.example".to_string()];
+        assert!(is_allowed_origin("https://jamfest.example", &allowed));
+
+        for origin in [
+            "https://elsewhere.example",
+            "https://localhost.example",
+            "http://127.0.0.1.example:80",
+            "null",
+            "file://",
+        ] {
+            assert!(!is_allowed_origin(origin, &allowed), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn only_streaming_parameters_are_forwarded() {
+        assert_eq!(
+            filter_query(
+                "encoding=linear16&callback=https://elsewhere.example&sample_rate=16000\
+                &keywords=sugar:2&keywords=mentos:2&extra=1&channels=1"
+            ),
+            "encoding=linear16&sample_rate=16000&keywords=sugar:2&keywords=mentos:2&channels=1"
+        );
+        assert_eq!(filter_query("callback=https://elsewhere.example"), "");
+        assert_eq!(filter_query(""), "");
+    }
+}
@@ -0,0 +1,763 @@
+//! Streaming audio to Deepgram over a websocket, as a `TranscriptionTransport`.
+use super::deepgram::{
+    self, ControlMessage, ListenOptions, Results, StreamingMessage, DEFAULT_LISTEN_URL,
+};
+use super::keywords::KeywordMatcher;
+use super::microphone::MicrophoneSource;
+use super::ring_buffer::RingBuffer;
+use super::speech::{
+    AudioBuffer, KeywordMapper, QueueCounts, SpeechBackend, SpeechConnectionStatus, SpeechError,
+    SpeechLatencySettings, Transcript, TranscriptWord, TranscriptionTransport,
+};
+use super::GameState;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+use std::collections::VecDeque;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+use native as platform;
+#[cfg(target_arch = "wasm32")]
+use web as platform;
+
+use platform::Connection;
+
+/// Listens to the microphone with Deepgram's streaming transcription.
+pub struct DeepgramBackend;
+
+impl SpeechBackend for DeepgramBackend {
+    type Source = MicrophoneSource;
+    type Transport = DeepgramTransport;
+    type Mapper = KeywordMapper;
+}
+
+/// How we'd like Deepgram to transcribe the game. The sample rate and keyword boosts are filled in
+/// when we connect. Insert a different `ListenOptions` resource after this plugin to change them.
+/// Set `DEEPGRAM_LISTEN_URL` when building to connect through the `deepgram-proxy` instead.
+fn game_listen_options() -> ListenOptions {
+    ListenOptions::new()
+        .base_url(option_env!("DEEPGRAM_LISTEN_URL").unwrap_or(DEFAULT_LISTEN_URL))
+        // `keywords` boosting isn't supported by nova-3
+        .model("nova-2")
+        .language("en-US")
+        // puzzle words are short, so finalize them quickly instead of waiting for a long pause
+        .endpointing(Some(300))
+        .smart_format(false)
+        .interim_results(true)
+        .utterance_end_ms(1000)
+}
+
+/// Where the game looks for Deepgram credentials, in order:
+/// - the token endpoint given by `DEEPGRAM_TOKEN_URL` when building, if any, in the browser
+/// - a `deepgram_api_key` query parameter on the page's URL
+/// - a `deepgram_api_key` entry in the browser's local storage
+/// - the `DEEPGRAM_API_KEY` environment variable, on the desktop
+/// - the `DEEPGRAM_API_KEY` given when building, if any. Don't ship a key you care about this way.
+///
+/// If none of them have credentials we connect without any, which is what the `deepgram-proxy`
+/// expects.
+fn game_credential_provider() -> CredentialProvider {
+    #[cfg(target_arch = "wasm32")]
+    let provider = {
+        let provider = match option_env!("DEEPGRAM_TOKEN_URL") {
+            Some(url) => CredentialProvider::new().endpoint(url),
+            None => CredentialProvider::new(),
+        };
+        provider
+            .query_parameter("deepgram_api_key")
+            .local_storage("deepgram_api_key")
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let provider = CredentialProvider::new().environment("DEEPGRAM_API_KEY");
+    provider.compile_time()
+}
+
+/// Something Deepgram will accept as proof that we may use it.
+enum Credential {
+    ApiKey(String),
+    /// A short-lived token, as handed out by Deepgram's `/v1/auth/grant`. Only token endpoints
+    /// hand these out, and the desktop doesn't support those yet.
+    #[cfg(target_arch = "wasm32")]
+    AccessToken(String),
+}
+
+/// Not every source is available on every platform, so only those the platform can use exist.
+#[derive(Clone, Debug)]
+enum CredentialSource {
+    #[cfg(target_arch = "wasm32")]
+    Endpoint(String),
+    #[cfg(target_arch = "wasm32")]
+    QueryParameter(String),
+    #[cfg(target_arch = "wasm32")]
+    LocalStorage(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    Environment(String),
+    CompileTime,
+    ApiKey(String),
+}
+
+/// Gets the credentials we connect to Deepgram with at runtime, rather than baking them into the
+/// game. Each source is tried in the order it was added until one has credentials.
+#[derive(Clone, Debug, Default)]
+pub struct CredentialProvider {
+    sources: Vec<CredentialSource>,
+}
+
+impl CredentialProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetch a short-lived access token from this URL. The endpoint should respond with JSON
+    /// containing an `access_token`, and is asked again for each connection. Only the browser
+    /// supports these.
+    #[cfg(target_arch = "wasm32")]
+    pub fn endpoint(mut self, url: impl Into<String>) -> Self {
+        self.sources.push(CredentialSource::Endpoint(url.into()));
+        self
+    }
+
+    /// Read an API key from this query parameter of the page's URL.
+    #[cfg(target_arch = "wasm32")]
+    pub fn query_parameter(mut self, name: impl Into<String>) -> Self {
+        self.sources
+            .push(CredentialSource::QueryParameter(name.into()));
+        self
+    }
+
+    /// Read an API key from this key in the browser's local storage.
+    #[cfg(target_arch = "wasm32")]
+    pub fn local_storage(mut self, key: impl Into<String>) -> Self {
+        self.sources
+            .push(CredentialSource::LocalStorage(key.into()));
+        self
+    }
+
+    /// Read an API key from this environment variable. Only the desktop has these.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn environment(mut self, name: impl Into<String>) -> Self {
+        self.sources
+            .push(CredentialSource::Environment(name.into()));
+        self
+    }
+
+    /// Use this API key, e.g. in tests. As with `compile_time`, don't ship a key you care about
+    /// this way.
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.sources.push(CredentialSource::ApiKey(key.into()));
+        self
+    }
+
+    /// Use the `DEEPGRAM_API_KEY` the game was built with, if there was one.
+    pub fn compile_time(mut self) -> Self {
+        self.sources.push(CredentialSource::CompileTime);
+        self
+    }
+
+    /// Look for credentials in the background, sending whatever we find (or `None`) to `tx`.
+    fn request(&self, tx: crossbeam_channel::Sender<Option<Credential>>) {
+        platform::request_credential(self.sources.clone(), tx);
+    }
+}
+
+/// How long to wait before the first reconnection attempt. Each failed attempt doubles this, up
+/// to `MAX_RECONNECT_DELAY_SECONDS`.
+const INITIAL_RECONNECT_DELAY_SECONDS: f64 = 0.5;
+const MAX_RECONNECT_DELAY_SECONDS: f64 = 30.0;
+
+/// Transcripts wait for the vocabulary to load before they're matched, but there's no point
+/// keeping more than this many.
+const MAX_PENDING_TRANSCRIPTS: usize = 64;
+
+/// The close code we report when Deepgram refuses our credentials, which it does before the
+/// websocket opens. Codes from 4000 up are for applications to use as they like.
+const UNAUTHORIZED_CLOSE_CODE: u16 = 4401;
+
+/// How long we can go without sending Deepgram anything before we send a `KeepAlive`.
+const KEEP_ALIVE_SECONDS: f64 = 4.0;
+
+/// We are using a non-send resource to handle the websocket client.
+/// See more here: https://bevy-cheatbook.github.io/programming/non-send.html
+impl FromWorld for DeepgramTransport {
+    fn from_world(world: &mut World) -> Self {
+        let max_pending_audio_seconds = world
+            .get_resource_or_insert_with(SpeechLatencySettings::default)
+            .max_transport_latency_seconds;
+        let (socket_events, socket_event_receiver) = crossbeam_channel::unbounded();
+
+        DeepgramTransport {
+            client: None,
+            connection_id: 0,
+            sample_rate: None,
+            pending_audio: VecDeque::new(),
+            max_pending_audio_seconds,
+            audio_counts: QueueCounts::default(),
+            transcripts: RingBuffer::with_length(MAX_PENDING_TRANSCRIPTS),
+            socket_events,
+            socket_event_receiver,
+            credential_request: None,
+            failed_attempts: 0,
+            next_attempt_at: 0.0,
+            last_sent_at: 0.0,
+            paused: false,
+            closing: false,
+        }
+    }
+}
+
+impl TranscriptionTransport for DeepgramTransport {
+    fn add_systems(app: &mut App) {
+        app.insert_resource(game_listen_options())
+            .insert_resource(game_credential_provider())
+            .add_system(connect_to_deepgram)
+            .add_system(update_connection_status)
+            .add_system(pause_listening)
+            .add_system(keep_deepgram_alive)
+            .add_system(proxy_audio_to_deepgram);
+    }
+
+    fn push_audio(&mut self, audio: AudioBuffer) {
+        if self.paused {
+            return;
+        }
+        self.pending_audio.push_back(audio);
+        self.trim_pending_audio();
+    }
+
+    fn try_transcript(&mut self) -> Option<Transcript> {
+        let (connection_id, results) = self.transcripts.pop()?;
+        Some(transcript(connection_id, &results))
+    }
+
+    fn counts(&self) -> QueueCounts {
+        self.audio_counts
+    }
+
+    fn recycled_audio(&mut self) -> Option<AudioBuffer> {
+        self.client.as_mut()?.spent_audio()
+    }
+}
+
+/// Only the words of the most likely alternative make it into the transcript. Each connection is a
+/// new stream whose timings start from zero.
+fn transcript(connection_id: u32, results: &Results) -> Transcript {
+    let alternative = match results.best_alternative() {
+        Some(alternative) => alternative,
+        None => {
+            return Transcript {
+                is_final: results.is_final,
+                stream: connection_id,
+                ..default()
+            }
+        }
+    };
+
+    Transcript {
+        text: alternative.transcript.clone(),
+        words: alternative
+            .words
+            .iter()
+            .map(|word| TranscriptWord {
+                word: word.word.clone(),
+                start: word.start,
+                end: word.end,
+                confidence: word.confidence,
+            })
+            .collect(),
+        is_final: results.is_final,
+        stream: connection_id,
+    }
+}
+
+/// We wait for the vocabulary to load before connecting, so that we can ask Deepgram to favour
+/// the puzzle keywords over words that sound like them. We also wait for the first audio from the
+/// microphone, since we have to tell Deepgram its sample rate. Credentials are asked for last, so
+/// that short-lived tokens are as fresh as possible.
+fn connect_to_deepgram(
+    mut transport: NonSendMut<DeepgramTransport>,
+    mut status: ResMut<SpeechConnectionStatus>,
+    listen_options: Res<ListenOptions>,
+    credential_provider: Res<CredentialProvider>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    time: Res<Time>,
+    mut errors: EventWriter<SpeechError>,
+) {
+    if transport.paused
+        || transport.client.is_some()
+        || time.seconds_since_startup() < transport.next_attempt_at
+    {
+        return;
+    }
+    let keyword_matcher = match keyword_matcher {
+        Some(keyword_matcher) => keyword_matcher,
+        None => return,
+    };
+    let sample_rate = match transport.pending_audio.front() {
+        Some(audio) => audio.sample_rate,
+        None => return,
+    };
+
+    let credential = match &transport.credential_request {
+        None => {
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            credential_provider.request(tx);
+            transport.credential_request = Some(rx);
+            *status = SpeechConnectionStatus::Connecting;
+            return;
+        }
+        Some(rx) => match rx.try_recv() {
+            Ok(credential) => credential,
+            Err(crossbeam_channel::TryRecvError::Empty) => return,
+            Err(crossbeam_channel::TryRecvError::Disconnected) => None,
+        },
+    };
+    transport.credential_request = None;
+
+    let options = listen_options
+        .clone()
+        .sample_rate(sample_rate)
+        .channels(1)
+        .keywords(keyword_matcher.boosts());
+
+    if credential.is_none() {
+        info!("No Deepgram credentials found, connecting without any.");
+    }
+    info!("Connecting to Deepgram at {} Hz.", sample_rate);
+
+    transport.connection_id += 1;
+    let client = Connection::open(
+        &options.url(),
+        credential.as_ref(),
+        transport.connection_id,
+        transport.socket_events.clone(),
+        transport.transcripts.clone(),
+    );
+    match client {
+        Ok(client) => {
+            transport.client = Some(client);
+            transport.sample_rate = Some(sample_rate);
+            *status = SpeechConnectionStatus::Connecting;
+        }
+        Err(error) => {
+            errors.send(SpeechError::Socket(error.clone()));
+            *status = SpeechConnectionStatus::Failed(error);
+            transport.schedule_reconnect(time.seconds_since_startup());
+        }
+    }
+}
+
+/// Something that happened to a websocket, tagged with the id of the connection it happened to.
+enum SocketEvent {
+    Open,
+    Error,
+    Close {
+        code: u16,
+        reason: String,
+        was_clean: bool,
+    },
+}
+
+/// Keep `SpeechConnectionStatus` up to date, and schedule a reconnection whenever the socket
+/// closes. Each failure in a row doubles the wait, so that we don't hammer Deepgram while it (or
+/// the network) is down.
+fn update_connection_status(
+    mut transport: NonSendMut<DeepgramTransport>,
+    mut status: ResMut<SpeechConnectionStatus>,
+    time: Res<Time>,
+    mut errors: EventWriter<SpeechError>,
+) {
+    let transport = &mut *transport;
+
+    while let Ok((connection_id, event)) = transport.socket_event_receiver.try_recv() {
+        // events from sockets we've already closed ourselves
+        if connection_id != transport.connection_id {
+            continue;
+        }
+
+        match event {
+            SocketEvent::Open => {
+                info!("Connected to Deepgram.");
+                transport.failed_attempts = 0;
+                transport.last_sent_at = time.seconds_since_startup();
+                *status = SpeechConnectionStatus::Open;
+            }
+            SocketEvent::Error => {
+                warn!("The connection to Deepgram failed.");
+            }
+            SocketEvent::Close {
+                code,
+                reason,
+                was_clean,
+            } => {
+                let opened = *status == SpeechConnectionStatus::Open;
+                let closed_by_us = transport.closing;
+                transport.client = None;
+                transport.sample_rate = None;
+                transport.closing = false;
+
+                *status = if was_clean && code == 1000 {
+                    info!("Deepgram closed the connection.");
+                    SpeechConnectionStatus::Closed
+                } else {
+                    let reason = if reason.is_empty() {
+                        format!("connection closed with code {}", code)
+                    } else {
+                        format!("{} (code {})", reason, code)
+                    };
+                    errors.send(if code == UNAUTHORIZED_CLOSE_CODE {
+                        SpeechError::BadCredentials
+                    } else {
+                        SpeechError::Socket(reason.clone())
+                    });
+                    SpeechConnectionStatus::Failed(reason)
+                };
+
+                if transport.paused {
+                    continue;
+                }
+                // we asked for this stream to be closed, and have been listening again since
+                if closed_by_us {
+                    transport.next_attempt_at = 0.0;
+                    continue;
+                }
+                // a connection that worked for a while shouldn't count against the next one
+                if opened {
+                    transport.failed_attempts = 0;
+                }
+                transport.schedule_reconnect(time.seconds_since_startup());
+            }
+        }
+    }
+}
+
+/// This will be a non-send resource, which is perfect for polling clients
+/// which poll in a bevy system which occurs once per frame ish.
+pub struct DeepgramTransport {
+    client: Option<Connection>,
+    /// Incremented for each new connection, so that events from old sockets can be told apart.
+    connection_id: u32,
+    /// The sample rate we told Deepgram to expect when we connected.
+    sample_rate: Option<u32>,
+    /// Audio from the microphone that hasn't been sent yet. While we're not connected we keep the
+    /// most recent audio to send once we are, and throw away anything older than this allows.
+    pending_audio: VecDeque<AudioBuffer>,
+    max_pending_audio_seconds: f32,
+    /// How many buffers of audio we've sent, and how many we've thrown away.
+    audio_counts: QueueCounts,
+    /// Every connection's message handler adds its transcripts here, tagged with its id.
+    transcripts: RingBuffer<(u32, Results)>,
+    /// Every connection's lifecycle handlers send their events here.
+    socket_events: crossbeam_channel::Sender<(u32, SocketEvent)>,
+    socket_event_receiver: crossbeam_channel::Receiver<(u32, SocketEvent)>,
+    /// Where the credentials for the next connection will arrive, once we've asked for them.
+    credential_request: Option<crossbeam_channel::Receiver<Option<Credential>>>,
+    /// How many connections in a row have closed without opening.
+    failed_attempts: u32,
+    /// Seconds since startup before which we shouldn't try to connect again.
+    next_attempt_at: f64,
+    /// Seconds since startup when we last sent audio or a `KeepAlive`.
+    last_sent_at: f64,
+    /// While paused we don't listen to the microphone, or reconnect if the connection drops.
+    paused: bool,
+    /// Whether we've asked Deepgram to close the stream. It sends what's left of the transcript
+    /// before it does, and no more audio can be sent in the meantime.
+    closing: bool,
+}
+
+impl DeepgramTransport {
+    /// Close the current connection ourselves, so that `connect_to_deepgram` opens a new one
+    /// straight away.
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.take() {
+            client.close();
+        }
+        self.sample_rate = None;
+        self.closing = false;
+        // so that the old socket's close event isn't mistaken for the new one's
+        self.connection_id += 1;
+    }
+
+    /// Stop listening. If we're connected, everything still waiting is sent, and then Deepgram is
+    /// asked to finish off what it has heard and close the stream, so that the last thing said
+    /// still arrives as a final transcript. Listening again opens a new stream.
+    fn pause(&mut self, now: f64, status: &mut SpeechConnectionStatus) -> Result<(), String> {
+        if self.paused {
+            return Ok(());
+        }
+        self.paused = true;
+        // nothing more will be said for now, so there's no need to connect
+        self.credential_request = None;
+
+        let sent = self.send_pending_audio(now);
+        self.pending_audio.clear();
+        sent?;
+        match &self.client {
+            Some(client) if client.is_open() && !self.closing => {
+                if let Err(error) = client.send_text(ControlMessage::CloseStream.to_json()) {
+                    self.disconnect();
+                    return Err(error);
+                }
+                self.closing = true;
+            }
+            // Deepgram is already finishing off the stream
+            Some(_) if self.closing => {}
+            _ => {
+                self.disconnect();
+                *status = SpeechConnectionStatus::Closed;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the audio waiting to go, in the order it was recorded. Whatever can't be sent yet stays
+    /// queued, and if the microphone's sample rate has changed the connection is closed so that
+    /// `connect_to_deepgram` can reconnect at the new rate.
+    fn send_pending_audio(&mut self, now: f64) -> Result<(), String> {
+        let client = match &mut self.client {
+            Some(client) if client.is_open() && !self.closing => client,
+            _ => return Ok(()),
+        };
+
+        while let Some(audio) = self.pending_audio.pop_front() {
+            if Some(audio.sample_rate) != self.sample_rate {
+                info!(
+                    "Audio sample rate changed to {} Hz, reconnecting to Deepgram.",
+                    audio.sample_rate
+                );
+                self.pending_audio.push_front(audio);
+                self.disconnect();
+                return Ok(());
+            }
+
+            match client.send_audio(audio) {
+                Ok(dropped) => self.audio_counts.dropped += dropped as u64,
+                Err((error, audio)) => {
+                    // the audio stays queued for the next connection
+                    self.pending_audio.push_front(audio);
+                    self.disconnect();
+                    return Err(error);
+                }
+            }
+            self.last_sent_at = now;
+            self.audio_counts.delivered += 1;
+        }
+        Ok(())
+    }
+
+    /// Wait a while before connecting again, doubling the wait with each failure in a row.
+    fn schedule_reconnect(&mut self, now: f64) {
+        let delay = (INITIAL_RECONNECT_DELAY_SECONDS * 2f64.powi(self.failed_attempts as i32))
+            .min(MAX_RECONNECT_DELAY_SECONDS);
+        self.failed_attempts += 1;
+        self.next_attempt_at = now + delay;
+        info!("Reconnecting to Deepgram in {:.1}s.", delay);
+    }
+
+    fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.next_attempt_at = 0.0;
+        }
+    }
+
+    /// Throw away the oldest audio until what's left is short enough to catch up on.
+    fn trim_pending_audio(&mut self) {
+        let mut pending_seconds: f32 = self.pending_audio.iter().map(AudioBuffer::duration).sum();
+        let mut dropped_seconds = 0.0;
+
+        while pending_seconds > self.max_pending_audio_seconds {
+            match self.pending_audio.pop_front() {
+                Some(audio) => {
+                    pending_seconds -= audio.duration();
+                    dropped_seconds += audio.duration();
+                    self.audio_counts.dropped += 1;
+                }
+                None => break,
+            }
+        }
+
+        if dropped_seconds > 0.0 {
+            debug!(
+                "Dropped {:.2}s of audio while waiting for Deepgram.",
+                dropped_seconds
+            );
+        }
+    }
+}
+
+/// Write linear16 samples into `bytes` in the little-endian order Deepgram expects, replacing
+/// whatever was there, so that one buffer can be reused for every message we send.
+pub fn pack_linear16(samples: &[i16], bytes: &mut Vec<u8>) {
+    bytes.clear();
+    bytes.reserve(samples.len() * 2);
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+}
+
+/// Parse a message from Deepgram and pass any transcript on to be matched against the keywords.
+fn handle_deepgram_message(
+    message: &str,
+    connection_id: u32,
+    transcripts: &RingBuffer<(u32, Results)>,
+) {
+    match deepgram::parse(message) {
+        Ok(StreamingMessage::Results(results)) => {
+            if transcripts.push((connection_id, results)) > 0 {
+                warn!("Dropped a transcript from Deepgram that the game didn't get to in time.");
+            }
+        }
+        Ok(StreamingMessage::Metadata(metadata)) => {
+            info!("Deepgram request id: {}.", metadata.request_id);
+        }
+        Ok(StreamingMessage::UtteranceEnd(utterance_end)) => {
+            debug!("Utterance ended at {}s.", utterance_end.last_word_end);
+        }
+        Ok(StreamingMessage::SpeechStarted(speech_started)) => {
+            debug!("Speech started at {}s.", speech_started.timestamp);
+        }
+        Ok(StreamingMessage::Error(error)) => {
+            error!(
+                "Deepgram error ({}): {} {}",
+                error.variant, error.description, error.message
+            );
+        }
+        Ok(StreamingMessage::Unknown) => {
+            debug!("Ignoring unknown message from Deepgram: {}", message);
+        }
+        Err(error) => {
+            warn!("Could not parse message from Deepgram: {}", error);
+        }
+    }
+}
+
+/// Audio is sent in the order it was recorded, and queued up while we aren't connected.
+fn proxy_audio_to_deepgram(
+    mut transport: NonSendMut<DeepgramTransport>,
+    mut status: ResMut<SpeechConnectionStatus>,
+    time: Res<Time>,
+    mut errors: EventWriter<SpeechError>,
+) {
+    if let Err(error) = transport.send_pending_audio(time.seconds_since_startup()) {
+        errors.send(SpeechError::Send(error.clone()));
+        *status = SpeechConnectionStatus::Failed(error);
+    }
+}
+
+/// Deepgram gives up on a stream that goes quiet, which happens whenever the microphone stops
+/// delivering audio, e.g. while the browser tab is hidden.
+fn keep_deepgram_alive(
+    mut transport: NonSendMut<DeepgramTransport>,
+    time: Res<Time>,
+    mut errors: EventWriter<SpeechError>,
+) {
+    let now = time.seconds_since_startup();
+    if now - transport.last_sent_at < KEEP_ALIVE_SECONDS {
+        return;
+    }
+
+    if let Some(client) = &transport.client {
+        // a closing stream doesn't need keeping alive
+        if client.is_open() && !transport.closing {
+            trace!("Sending KeepAlive to Deepgram.");
+            if let Err(error) = client.send_text(ControlMessage::KeepAlive.to_json()) {
+                errors.send(SpeechError::Send(error));
+            }
+            transport.last_sent_at = now;
+        }
+    }
+}
+
+/// We stop listening while the game window is in the background, once the player has won, and
+/// when the game exits, closing the stream each time so that nothing already said is lost. When
+/// the window comes back to the foreground we start a new stream.
+fn pause_listening(
+    mut transport: NonSendMut<DeepgramTransport>,
+    mut status: ResMut<SpeechConnectionStatus>,
+    time: Res<Time>,
+    // there's no game to win when the speech plugin is used on its own, e.g. in tests
+    game_state: Option<Res<GameState>>,
+    mut focus_events: EventReader<WindowFocused>,
+    mut exit_events: EventReader<AppExit>,
+    mut errors: EventWriter<SpeechError>,
+) {
+    let won = game_state.is_some_and(|game_state| game_state.treasure_chest_opened);
+    let focused = focus_events.iter().last().map(|event| event.focused);
+    let paused = if exit_events.iter().next().is_some() || won {
+        true
+    } else {
+        match focused {
+            Some(focused) => !focused,
+            None => return,
+        }
+    };
+
+    if !paused {
+        transport.resume();
+    } else if let Err(error) = transport.pause(time.seconds_since_startup(), &mut status) {
+        warn!("Could not close the Deepgram stream: {}", error);
+        errors.send(SpeechError::Send(error.clone()));
+        *status = SpeechConnectionStatus::Failed(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deepgram::fixtures::*;
+
+    /// Every kind of message is handled without panicking, and only results are kept to be
+    /// matched against the keywords.
+    #[test]
+    fn only_results_are_kept() {
+        let transcripts = RingBuffer::with_length(MAX_PENDING_TRANSCRIPTS);
+        for message in [
+            INTERIM_RESULTS,
+            METADATA,
+            SPEECH_STARTED,
+            FINAL_RESULTS,
+            UTTERANCE_END,
+            ERROR,
+            UNTAGGED_ERROR,
+            UNKNOWN,
+            MALFORMED,
+        ] {
+            handle_deepgram_message(message, 1, &transcripts);
+        }
+
+        let (connection_id, results) = transcripts.pop().expect("the interim results");
+        let interim = transcript(connection_id, &results);
+        assert_eq!(interim.stream, 1);
+        assert!(!interim.is_final);
+        assert_eq!(interim.text, "sugar");
+
+        let (connection_id, results) = transcripts.pop().expect("the final results");
+        let last = transcript(connection_id, &results);
+        assert!(last.is_final);
+        assert_eq!(last.text, "Sugar, please.");
+        assert_eq!(
+            last.words,
+            [
+                TranscriptWord {
+                    word: "sugar".to_string(),
+                    start: 0.24,
+                    end: 0.72,
+                    confidence: 0.99,
+                },
+                TranscriptWord {
+                    word: "please".to_string(),
+                    start: 0.8,
+                    end: 1.2,
+                    confidence: 0.97,
+                },
+            ]
+        );
+
+        assert!(transcripts.pop().is_none());
+    }
+}
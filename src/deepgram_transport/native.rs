@@ -0,0 +1,298 @@
+//! The desktop half of the Deepgram connection. The websocket lives on its own thread, which
+//! reports back over the same channels the browser's event handlers use.
+use super::{
+    handle_deepgram_message, pack_linear16, Credential, CredentialSource, SocketEvent,
+    UNAUTHORIZED_CLOSE_CODE,
+};
+use crate::deepgram::Results;
+use crate::ring_buffer::RingBuffer;
+use crate::speech::AudioBuffer;
+use bevy::prelude::*;
+use std::io;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::client::IntoClientRequest;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::Message;
+
+/// How long the socket thread waits for a message from Deepgram before checking for audio to
+/// send. This bounds the latency it adds.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many messages of audio can wait for the socket thread, e.g. while the network is slow, which
+/// is a couple of seconds' worth. Beyond that the oldest are thrown away rather than sent late.
+const MAX_QUEUED_AUDIO_MESSAGES: usize = 40;
+
+const CLOSED: &str = "the connection has already closed";
+
+/// What the game asks the socket thread to do, besides sending audio.
+enum Command {
+    Text(String),
+    Close,
+}
+
+/// A websocket connection to Deepgram.
+pub(super) struct Connection {
+    commands: crossbeam_channel::Sender<Command>,
+    /// Audio waiting to be sent. The socket thread sends it before any commands that came after
+    /// it, so that `CloseStream` still comes last.
+    audio: RingBuffer<AudioBuffer>,
+    /// Where the socket thread hands back audio it's sent, for the buffers to be used again.
+    spent_audio: crossbeam_channel::Receiver<AudioBuffer>,
+    open: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Start connecting. The socket's open, error and close events are sent to `socket_events`
+    /// tagged with `connection_id`, and transcripts are added to `transcripts` tagged likewise.
+    /// Dropping the connection closes it. Connecting happens on another thread, so this can't fail
+    /// here.
+    pub(super) fn open(
+        url: &str,
+        credential: Option<&Credential>,
+        connection_id: u32,
+        socket_events: crossbeam_channel::Sender<(u32, SocketEvent)>,
+        transcripts: RingBuffer<(u32, Results)>,
+    ) -> Result<Self, String> {
+        let (commands, command_receiver) = crossbeam_channel::unbounded();
+        let audio = RingBuffer::with_length(MAX_QUEUED_AUDIO_MESSAGES);
+        let (spent_audio_sender, spent_audio) =
+            crossbeam_channel::bounded(MAX_QUEUED_AUDIO_MESSAGES);
+        let open = Arc::new(AtomicBool::new(false));
+
+        let url = url.to_string();
+        let authorization = credential.map(Credential::authorization);
+        let socket_open = open.clone();
+        let game_queues = GameQueues {
+            audio: audio.clone(),
+            spent_audio: spent_audio_sender,
+            commands: command_receiver,
+        };
+        thread::spawn(move || {
+            let close = run_socket(
+                &url,
+                authorization,
+                &game_queues,
+                &socket_open,
+                |event| {
+                    let _ = socket_events.send((connection_id, event));
+                },
+                |message| handle_deepgram_message(message, connection_id, &transcripts),
+            );
+            socket_open.store(false, Ordering::Relaxed);
+            let _ = socket_events.send((connection_id, close));
+        });
+
+        Ok(Connection {
+            commands,
+            audio,
+            spent_audio,
+            open,
+        })
+    }
+
+    pub(super) fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Hand `audio` to the socket thread, which packs it into the message itself. Returns how many
+    /// older buffers of audio were thrown away to make room for it, or the audio back if the
+    /// connection has closed.
+    pub(super) fn send_audio(
+        &mut self,
+        audio: AudioBuffer,
+    ) -> Result<usize, (String, AudioBuffer)> {
+        if self.audio.is_abandoned() {
+            return Err((CLOSED.to_string(), audio));
+        }
+        Ok(self.audio.push(audio))
+    }
+
+    /// Audio that's been sent, whose buffer can be filled again.
+    pub(super) fn spent_audio(&mut self) -> Option<AudioBuffer> {
+        self.spent_audio.try_recv().ok()
+    }
+
+    pub(super) fn send_text(&self, text: &str) -> Result<(), String> {
+        self.send(Command::Text(text.to_string()))
+    }
+
+    pub(super) fn close(&self) {
+        let _ = self.commands.send(Command::Close);
+    }
+
+    fn send(&self, command: Command) -> Result<(), String> {
+        self.commands.send(command).map_err(|_| CLOSED.to_string())
+    }
+}
+
+impl Credential {
+    /// The value of the `Authorization` header that carries this credential.
+    fn authorization(&self) -> String {
+        match self {
+            Credential::ApiKey(key) => format!("Token {}", key),
+        }
+    }
+}
+
+/// The socket thread's ends of the queues it shares with the game.
+struct GameQueues {
+    audio: RingBuffer<AudioBuffer>,
+    spent_audio: crossbeam_channel::Sender<AudioBuffer>,
+    commands: crossbeam_channel::Receiver<Command>,
+}
+
+/// Connect, then pass audio, commands and messages back and forth until the socket closes. Returns
+/// the close event to report.
+fn run_socket(
+    url: &str,
+    authorization: Option<String>,
+    game_queues: &GameQueues,
+    open: &AtomicBool,
+    send_event: impl Fn(SocketEvent),
+    handle_message: impl Fn(&str),
+) -> SocketEvent {
+    let failed = |reason: String| {
+        send_event(SocketEvent::Error);
+        SocketEvent::Close {
+            code: 1006,
+            reason,
+            was_clean: false,
+        }
+    };
+
+    let mut request = match url.into_client_request() {
+        Ok(request) => request,
+        Err(error) => return failed(error.to_string()),
+    };
+    if let Some(authorization) = authorization {
+        match authorization.parse() {
+            Ok(value) => {
+                request.headers_mut().insert("Authorization", value);
+            }
+            Err(_) => return failed("the credentials aren't a valid header".to_string()),
+        }
+    }
+
+    let mut socket = match tungstenite::connect(request) {
+        Ok((socket, _)) => socket,
+        Err(tungstenite::Error::Http(response))
+            if matches!(response.status().as_u16(), 401 | 403) =>
+        {
+            send_event(SocketEvent::Error);
+            return SocketEvent::Close {
+                code: UNAUTHORIZED_CLOSE_CODE,
+                reason: format!("HTTP {}", response.status()),
+                was_clean: false,
+            };
+        }
+        Err(error) => return failed(error.to_string()),
+    };
+    let timeout = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => set_read_timeout(stream),
+        MaybeTlsStream::Rustls(stream) => set_read_timeout(stream.get_ref()),
+        _ => Ok(()),
+    };
+    if let Err(error) = timeout {
+        return failed(error.to_string());
+    }
+
+    open.store(true, Ordering::Relaxed);
+    send_event(SocketEvent::Open);
+
+    let mut closing = false;
+    let mut close_frame = None;
+    loop {
+        while !closing {
+            if let Some(audio) = game_queues.audio.pop() {
+                // tungstenite copies the message into its own write buffer and frees it, so unlike
+                // the samples the bytes can't come back to be reused: packing into an empty Vec
+                // keeps it to the one allocation of the right size per message
+                let mut bytes = Vec::new();
+                pack_linear16(&audio.samples, &mut bytes);
+                let _ = game_queues.spent_audio.try_send(audio);
+                if let Err(error) = socket.write_message(Message::Binary(bytes)) {
+                    return failed(error.to_string());
+                }
+                continue;
+            }
+
+            let result = match game_queues.commands.try_recv() {
+                Ok(Command::Text(text)) => socket.write_message(Message::Text(text)),
+                // the game has dropped the connection, so nobody is listening any more
+                Ok(Command::Close) | Err(crossbeam_channel::TryRecvError::Disconnected) => {
+                    closing = true;
+                    socket.close(None)
+                }
+                Err(crossbeam_channel::TryRecvError::Empty) => break,
+            };
+            if let Err(error) = result {
+                return failed(error.to_string());
+            }
+        }
+
+        match socket.read_message() {
+            Ok(Message::Text(message)) => {
+                trace!("Received a message from Deepgram: {:?}", message);
+                handle_message(&message);
+            }
+            Ok(Message::Close(frame)) => close_frame = frame,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(error))
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed) => {
+                // 1005 is what browsers report when the close frame has no status code
+                let (code, reason) = close_frame.map_or((1005, String::new()), |frame| {
+                    (frame.code.into(), frame.reason.into_owned())
+                });
+                return SocketEvent::Close {
+                    code,
+                    reason,
+                    was_clean: true,
+                };
+            }
+            Err(error) => return failed(error.to_string()),
+        }
+    }
+}
+
+fn set_read_timeout(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))
+}
+
+/// Look for credentials, sending whatever we find (or `None`) to `tx`. None of the sources have
+/// to wait for anything on the desktop, so this answers straight away.
+pub(super) fn request_credential(
+    sources: Vec<CredentialSource>,
+    tx: crossbeam_channel::Sender<Option<Credential>>,
+) {
+    let credential = sources.iter().find_map(|source| match credential(source) {
+        Ok(credential) => credential,
+        Err(error) => {
+            warn!(
+                "Could not get Deepgram credentials from {:?}: {}",
+                source, error
+            );
+            None
+        }
+    });
+    let _ = tx.send(credential);
+}
+
+/// `Ok(None)` means this source simply doesn't have any credentials for us.
+fn credential(source: &CredentialSource) -> Result<Option<Credential>, String> {
+    match source {
+        CredentialSource::Environment(name) => Ok(std::env::var(name).ok().map(Credential::ApiKey)),
+        CredentialSource::CompileTime => {
+            Ok(option_env!("DEEPGRAM_API_KEY").map(|key| Credential::ApiKey(key.to_string())))
+        }
+        CredentialSource::ApiKey(key) => Ok(Some(Credential::ApiKey(key.clone()))),
+    }
+}
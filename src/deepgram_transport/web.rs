@@ -0,0 +1,239 @@
+//! The browser half of the Deepgram connection, built on the browser's own `WebSocket` and
+//! `fetch`. Everything here runs on the main thread and reports back over channels.
+use super::{handle_deepgram_message, pack_linear16, Credential, CredentialSource, SocketEvent};
+use crate::deepgram::Results;
+use crate::ring_buffer::RingBuffer;
+use crate::speech::AudioBuffer;
+use bevy::prelude::*;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{CloseEvent, MessageEvent, Response, UrlSearchParams, WebSocket};
+
+/// A websocket connection to Deepgram.
+pub(super) struct Connection {
+    client: WebSocket,
+    /// The audio message being sent, kept to save allocating one each time.
+    outgoing: Vec<u8>,
+    /// Audio that's been sent, since the browser copies it as soon as we send it.
+    spent_audio: Vec<AudioBuffer>,
+}
+
+impl Connection {
+    /// Start connecting, unless the browser won't even try, e.g. because the URL is invalid. The
+    /// socket's open, error and close events are sent to `socket_events` tagged with
+    /// `connection_id`, and transcripts are added to `transcripts` tagged likewise.
+    pub(super) fn open(
+        url: &str,
+        credential: Option<&Credential>,
+        connection_id: u32,
+        socket_events: crossbeam_channel::Sender<(u32, SocketEvent)>,
+        transcripts: RingBuffer<(u32, Results)>,
+    ) -> Result<Self, String> {
+        let client = match credential {
+            Some(credential) => {
+                let protocols = serde_wasm_bindgen::to_value(&credential.protocols())
+                    .map_err(|error| error.to_string())?;
+                WebSocket::new_with_str_sequence(url, &protocols)
+            }
+            None => WebSocket::new(url),
+        }
+        .map_err(js_error)?;
+
+        set_message_handler(&client, connection_id, transcripts);
+        set_lifecycle_handlers(&client, connection_id, socket_events);
+
+        Ok(Connection {
+            client,
+            outgoing: Vec::new(),
+            spent_audio: Vec::new(),
+        })
+    }
+
+    pub(super) fn is_open(&self) -> bool {
+        self.client.ready_state() == WebSocket::OPEN
+    }
+
+    /// The browser buffers whatever we send it, so no audio is ever thrown away here. Returns the
+    /// audio back if it couldn't be sent.
+    pub(super) fn send_audio(
+        &mut self,
+        audio: AudioBuffer,
+    ) -> Result<usize, (String, AudioBuffer)> {
+        pack_linear16(&audio.samples, &mut self.outgoing);
+        if let Err(error) = self.client.send_with_u8_array(&self.outgoing) {
+            return Err((js_error(error), audio));
+        }
+        self.spent_audio.push(audio);
+        Ok(0)
+    }
+
+    /// Audio that's been sent, whose buffer can be filled again.
+    pub(super) fn spent_audio(&mut self) -> Option<AudioBuffer> {
+        self.spent_audio.pop()
+    }
+
+    pub(super) fn send_text(&self, text: &str) -> Result<(), String> {
+        self.client.send_with_str(text).map_err(js_error)
+    }
+
+    pub(super) fn close(&self) {
+        let _ = self.client.close();
+    }
+}
+
+impl Credential {
+    /// The websocket subprotocols that carry this credential, since browsers won't let us set an
+    /// `Authorization` header.
+    fn protocols(&self) -> [&str; 2] {
+        match self {
+            Credential::ApiKey(key) => ["token", key],
+            Credential::AccessToken(token) => ["bearer", token],
+        }
+    }
+}
+
+fn set_message_handler(
+    client: &WebSocket,
+    connection_id: u32,
+    transcripts: RingBuffer<(u32, Results)>,
+) {
+    // We're going to create a closure to receive websocket messages on. We can't just move an
+    // `EventWriter` into that closure to send messages from because the `EventWriter` is tied
+    // to the lifetime of the global `Events` queue and we can't easily communicate that this
+    // closure will outlive that. So instead we create a channel pair and push messages from
+    // the `tx` to the `rx` and then, in a separate system, we read from the `rx` and write to
+    // the `EventWriter`.
+    let closure = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
+        if let Ok(message) = e.data().dyn_into::<js_sys::JsString>() {
+            trace!("Received a message from Deepgram: {:?}", message);
+            handle_deepgram_message(&String::from(message), connection_id, &transcripts);
+        }
+    });
+    client.set_onmessage(Some(closure.as_ref().unchecked_ref()));
+
+    // We need to forget this on the Rust side. If we didn't then, when this function finished,
+    // the `Closure` object (which is only passed _by reference_ to `set_onmessage`) would also
+    // be dropped. This leaks the closure so that it sticks around and is valid when we later
+    // receive messages on the websocket.
+    closure.forget();
+}
+
+fn set_lifecycle_handlers(
+    client: &WebSocket,
+    connection_id: u32,
+    socket_events: crossbeam_channel::Sender<(u32, SocketEvent)>,
+) {
+    // As with the message handler, these closures outlive this function, so they report back over
+    // a channel and are leaked with `forget`. A receiver only goes away when the game does, so
+    // there is nothing useful to do if sending fails.
+    let events = socket_events.clone();
+    let on_open = Closure::<dyn FnMut()>::new(move || {
+        let _ = events.send((connection_id, SocketEvent::Open));
+    });
+    client.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    on_open.forget();
+
+    // The browser deliberately doesn't say what went wrong, but a close event always follows.
+    let events = socket_events.clone();
+    let on_error = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        let _ = events.send((connection_id, SocketEvent::Error));
+    });
+    client.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    on_error.forget();
+
+    let on_close = Closure::<dyn FnMut(_)>::new(move |e: CloseEvent| {
+        let _ = socket_events.send((
+            connection_id,
+            SocketEvent::Close {
+                code: e.code(),
+                reason: e.reason(),
+                was_clean: e.was_clean(),
+            },
+        ));
+    });
+    client.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+    on_close.forget();
+}
+
+/// Look for credentials in the background, sending whatever we find (or `None`) to `tx`.
+pub(super) fn request_credential(
+    sources: Vec<CredentialSource>,
+    tx: crossbeam_channel::Sender<Option<Credential>>,
+) {
+    wasm_bindgen_futures::spawn_local(async move {
+        for source in &sources {
+            match credential(source).await {
+                Ok(Some(credential)) => {
+                    let _ = tx.send(Some(credential));
+                    return;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    warn!(
+                        "Could not get Deepgram credentials from {:?}: {}",
+                        source, error
+                    );
+                }
+            }
+        }
+        let _ = tx.send(None);
+    });
+}
+
+/// `Ok(None)` means this source simply doesn't have any credentials for us.
+async fn credential(source: &CredentialSource) -> Result<Option<Credential>, String> {
+    let window = web_sys::window().ok_or("there is no window")?;
+
+    match source {
+        CredentialSource::Endpoint(url) => fetch_access_token(&window, url).await.map(Some),
+        CredentialSource::QueryParameter(name) => {
+            let search = window.location().search().map_err(js_error)?;
+            let parameters = UrlSearchParams::new_with_str(&search).map_err(js_error)?;
+            Ok(parameters.get(name).map(Credential::ApiKey))
+        }
+        CredentialSource::LocalStorage(key) => match window.local_storage().map_err(js_error)? {
+            Some(storage) => Ok(storage
+                .get_item(key)
+                .map_err(js_error)?
+                .map(Credential::ApiKey)),
+            None => Ok(None),
+        },
+        CredentialSource::CompileTime => {
+            Ok(option_env!("DEEPGRAM_API_KEY").map(|key| Credential::ApiKey(key.to_string())))
+        }
+        CredentialSource::ApiKey(key) => Ok(Some(Credential::ApiKey(key.clone()))),
+    }
+}
+
+/// The body of a successful response from a token endpoint. This is what Deepgram's
+/// `/v1/auth/grant` returns, so an endpoint can pass that response on as it is.
+#[derive(serde::Deserialize)]
+struct TokenGrant {
+    access_token: String,
+}
+
+async fn fetch_access_token(window: &web_sys::Window, url: &str) -> Result<Credential, String> {
+    let response: Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if !response.ok() {
+        return Err(format!("{} responded with {}", url, response.status()));
+    }
+
+    let body = JsFuture::from(response.text().map_err(js_error)?)
+        .await
+        .map_err(js_error)?
+        .as_string()
+        .ok_or("the response wasn't text")?;
+    let grant: TokenGrant = serde_json::from_str(&body).map_err(|error| error.to_string())?;
+
+    Ok(Credential::AccessToken(grant.access_token))
+}
+
+fn js_error(value: JsValue) -> String {
+    format!("{:?}", value)
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+use crate::{SpeechEvent, TentativeSpeechEvent};
+
+/// How long a heard keyword stays on screen.
+const HEARD_KEYWORD_SECONDS: f32 = 2.0;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HeardKeywordTimer(Timer::from_seconds(
+            HEARD_KEYWORD_SECONDS,
+            false,
+        )))
+        .add_startup_system(spawn_heard_keyword_text)
+        .add_system(show_heard_keywords);
+    }
+}
+
+#[derive(Component)]
+struct HeardKeywordText;
+
+struct HeardKeywordTimer(Timer);
+
+fn spawn_heard_keyword_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("kongtext.ttf"),
+                    font_size: 12.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    bottom: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(HeardKeywordText);
+}
+
+/// Tentative keywords are shown greyed out with a question mark as soon as they are heard, and
+/// turn white once the recognizer commits to them.
+fn show_heard_keywords(
+    time: Res<Time>,
+    mut timer: ResMut<HeardKeywordTimer>,
+    mut tentative_speech_events: EventReader<TentativeSpeechEvent>,
+    mut speech_events: EventReader<SpeechEvent>,
+    mut text_query: Query<&mut Text, With<HeardKeywordText>>,
+) {
+    let mut text = text_query.single_mut();
+
+    for TentativeSpeechEvent(event) in tentative_speech_events.iter() {
+        text.sections[0].value = format!("{}?", event.keyword.name());
+        text.sections[0].style.color = Color::GRAY;
+        timer.0.reset();
+    }
+
+    for event in speech_events.iter() {
+        text.sections[0].value = format!("{}!", event.keyword.name());
+        text.sections[0].style.color = Color::WHITE;
+        timer.0.reset();
+    }
+
+    if timer.0.tick(time.delta()).just_finished() {
+        text.sections[0].value.clear();
+    }
+}
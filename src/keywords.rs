@@ -0,0 +1,464 @@
+//! Matching transcribed words against the puzzle keywords. This module knows nothing about Bevy
+//! or Deepgram: it takes a `Vocabulary` and a list of words and tells you which keywords were said.
+use serde::Deserialize;
+
+/// The words the game listens for, as loaded from the vocabulary asset.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Vocabulary {
+    pub keywords: Vec<KeywordEntry>,
+    #[serde(default)]
+    pub matching: MatchOptions,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct KeywordEntry {
+    /// The canonical spelling, which is what the rest of the game refers to.
+    pub keyword: String,
+    /// Other spellings and common misrecognitions, which may span several words ("men toes").
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// How much to favour this keyword over similar sounding words when transcribing. Only the
+    /// canonical spelling is boosted, since boosting misrecognitions would make them more likely.
+    #[serde(default = "default_boost")]
+    pub boost: f32,
+}
+
+fn default_boost() -> f32 {
+    2.0
+}
+
+/// How forgiving the matcher is. The defaults only accept exact keywords and aliases.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MatchOptions {
+    /// Words whose similarity (one minus the Levenshtein distance divided by the length of the
+    /// longer word) to a keyword or alias is at least this much are a match. `1.0` disables it.
+    pub min_similarity: f32,
+    /// When set, words with the same Soundex code as a keyword, i.e. that sound like it, are a
+    /// match if they are at least `min_phonetic_similarity` similar to it.
+    pub phonetic: bool,
+    pub min_phonetic_similarity: f32,
+    /// Short words are too easily confused, so words shorter than this must match exactly.
+    pub min_fuzzy_length: usize,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            min_similarity: 1.0,
+            phonetic: false,
+            min_phonetic_similarity: 1.0,
+            min_fuzzy_length: 4,
+        }
+    }
+}
+
+/// How a word came to match a keyword, from most to least certain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    Exact,
+    Alias,
+    EditDistance,
+    Phonetic,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeywordMatch<'a> {
+    pub keyword: &'a str,
+    /// Index of the first matching word in the words passed to `KeywordMatcher::find_matches`.
+    pub word_index: usize,
+    /// How many words the match spans, which is more than one for multi-word aliases.
+    pub word_count: usize,
+    pub similarity: f32,
+    pub kind: MatchKind,
+}
+
+/// One spelling of a keyword, normalized and ready to compare against.
+struct Form {
+    keyword: usize,
+    text: String,
+    word_count: usize,
+    phonetic_code: Option<String>,
+    kind: MatchKind,
+}
+
+/// A `Vocabulary` compiled for matching. Build one whenever the vocabulary changes.
+pub struct KeywordMatcher {
+    keywords: Vec<String>,
+    // only Deepgram can be biased towards keywords
+    boosts: Vec<f32>,
+    forms: Vec<Form>,
+    options: MatchOptions,
+    max_word_count: usize,
+}
+
+impl KeywordMatcher {
+    pub fn new(vocabulary: &Vocabulary) -> Self {
+        let options = vocabulary.matching.clone();
+        let mut keywords = Vec::new();
+        let mut boosts = Vec::new();
+        let mut forms = Vec::new();
+
+        for (index, entry) in vocabulary.keywords.iter().enumerate() {
+            keywords.push(entry.keyword.clone());
+            boosts.push(entry.boost);
+
+            let spellings = std::iter::once((&entry.keyword, MatchKind::Exact))
+                .chain(entry.aliases.iter().map(|alias| (alias, MatchKind::Alias)));
+            for (spelling, kind) in spellings {
+                let words: Vec<String> = spelling
+                    .split_whitespace()
+                    .map(normalize)
+                    .filter(|word| !word.is_empty())
+                    .collect();
+                if words.is_empty() {
+                    continue;
+                }
+
+                let phonetic_code = match words.as_slice() {
+                    [word] if options.phonetic => soundex(word),
+                    _ => None,
+                };
+                forms.push(Form {
+                    keyword: index,
+                    text: words.join(" "),
+                    word_count: words.len(),
+                    phonetic_code,
+                    kind,
+                });
+            }
+        }
+
+        let max_word_count = forms.iter().map(|form| form.word_count).max().unwrap_or(0);
+
+        KeywordMatcher {
+            keywords,
+            boosts,
+            forms,
+            options,
+            max_word_count,
+        }
+    }
+
+    /// The canonical spelling of each keyword.
+    // only the offline keyword spotter needs the keywords on their own
+    pub fn keywords(&self) -> impl Iterator<Item = &str> {
+        self.keywords.iter().map(String::as_str)
+    }
+
+    /// Every spelling of every keyword, aliases included, normalized as they're matched.
+    pub fn spellings(&self) -> impl Iterator<Item = &str> {
+        self.forms.iter().map(|form| form.text.as_str())
+    }
+
+    /// Each keyword with a positive boost, and its boost, for biasing the recognizer towards them.
+    pub fn boosts(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.keywords
+            .iter()
+            .zip(&self.boosts)
+            .filter(|(_, &boost)| boost > 0.0)
+            .map(|(keyword, &boost)| (keyword.as_str(), boost))
+    }
+
+    /// Find every keyword in a sequence of words. Matches never overlap; when several spellings
+    /// match at the same word, the most similar one wins, then the one spanning the most words.
+    pub fn find_matches<S: AsRef<str>>(&self, words: &[S]) -> Vec<KeywordMatch<'_>> {
+        let words: Vec<String> = words.iter().map(|word| normalize(word.as_ref())).collect();
+        let mut matches = Vec::new();
+
+        let mut index = 0;
+        while index < words.len() {
+            let longest = self.max_word_count.min(words.len() - index);
+            let best = (1..=longest)
+                .filter(|&count| words[index..index + count].iter().all(|w| !w.is_empty()))
+                .filter_map(|count| {
+                    let phrase = words[index..index + count].join(" ");
+                    self.match_phrase(&phrase, count)
+                })
+                .max_by(|a, b| {
+                    a.similarity
+                        .total_cmp(&b.similarity)
+                        .then(a.word_count.cmp(&b.word_count))
+                });
+
+            match best {
+                Some(mut keyword_match) => {
+                    keyword_match.word_index = index;
+                    index += keyword_match.word_count;
+                    matches.push(keyword_match);
+                }
+                None => index += 1,
+            }
+        }
+
+        matches
+    }
+
+    fn match_phrase(&self, phrase: &str, word_count: usize) -> Option<KeywordMatch<'_>> {
+        let fuzzy = phrase.chars().count() >= self.options.min_fuzzy_length;
+        let phonetic_code = match word_count {
+            1 if self.options.phonetic && fuzzy => soundex(phrase),
+            _ => None,
+        };
+
+        self.forms
+            .iter()
+            .filter(|form| form.word_count == word_count)
+            .filter_map(|form| {
+                if form.text == phrase {
+                    return Some((form, 1.0, form.kind));
+                }
+                if !fuzzy {
+                    return None;
+                }
+
+                let similarity = strsim::normalized_levenshtein(&form.text, phrase) as f32;
+                if similarity >= self.options.min_similarity {
+                    Some((form, similarity, MatchKind::EditDistance))
+                } else if phonetic_code.is_some()
+                    && form.phonetic_code == phonetic_code
+                    && similarity >= self.options.min_phonetic_similarity
+                {
+                    Some((form, similarity, MatchKind::Phonetic))
+                } else {
+                    None
+                }
+            })
+            .max_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+            .map(|(form, similarity, kind)| KeywordMatch {
+                keyword: &self.keywords[form.keyword],
+                word_index: 0,
+                word_count,
+                similarity,
+                kind,
+            })
+    }
+}
+
+/// Lowercase a word and strip everything but letters and digits, so "Mento's," becomes "mentos".
+pub fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The American Soundex code of a word, e.g. "R163" for "Robert", or `None` if it has no letters.
+pub fn soundex(word: &str) -> Option<String> {
+    let mut letters = word
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_uppercase());
+
+    let first = letters.next()?;
+    let mut code = String::with_capacity(4);
+    code.push(first);
+
+    let mut previous = soundex_digit(first);
+    for letter in letters {
+        let digit = soundex_digit(letter);
+        if digit.is_some() && digit != previous {
+            code.extend(digit);
+            if code.len() == 4 {
+                break;
+            }
+        }
+        // vowels separate letters with the same code, but 'H' and 'W' do not
+        if letter != 'H' && letter != 'W' {
+            previous = digit;
+        }
+    }
+
+    while code.len() < 4 {
+        code.push('0');
+    }
+
+    Some(code)
+}
+
+fn soundex_digit(letter: char) -> Option<char> {
+    match letter {
+        'B' | 'F' | 'P' | 'V' => Some('1'),
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+        'D' | 'T' => Some('3'),
+        'L' => Some('4'),
+        'M' | 'N' => Some('5'),
+        'R' => Some('6'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(keyword: &str, aliases: &[&str]) -> KeywordEntry {
+        KeywordEntry {
+            keyword: keyword.to_string(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            boost: default_boost(),
+        }
+    }
+
+    /// The puzzle words, with the same fuzziness as the vocabulary asset.
+    fn fuzzy_options() -> MatchOptions {
+        MatchOptions {
+            min_similarity: 0.8,
+            phonetic: true,
+            min_phonetic_similarity: 0.6,
+            min_fuzzy_length: 4,
+        }
+    }
+
+    fn puzzle_matcher(matching: MatchOptions) -> KeywordMatcher {
+        KeywordMatcher::new(&Vocabulary {
+            keywords: vec![
+                entry("sugar", &["shugar"]),
+                entry("mentos", &["mentors", "men toes"]),
+                entry("bridge", &[]),
+            ],
+            matching,
+        })
+    }
+
+    fn find(matcher: &KeywordMatcher, text: &str) -> Vec<(String, MatchKind)> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        matcher
+            .find_matches(&words)
+            .into_iter()
+            .map(|keyword_match| (keyword_match.keyword.to_string(), keyword_match.kind))
+            .collect()
+    }
+
+    #[test]
+    fn exact_matches() {
+        let matcher = puzzle_matcher(MatchOptions::default());
+        let words = ["Please", "pass", "the", "Sugar,", "and", "the", "bridge"];
+        let matches = matcher.find_matches(&words);
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].keyword, "sugar");
+        assert_eq!(matches[0].kind, MatchKind::Exact);
+        assert_eq!(matches[0].word_index, 3);
+        assert_eq!(matches[0].word_count, 1);
+        assert_eq!(matches[0].similarity, 1.0);
+        assert_eq!(matches[1].keyword, "bridge");
+        assert_eq!(matches[1].word_index, 6);
+    }
+
+    #[test]
+    fn aliases() {
+        let matcher = puzzle_matcher(MatchOptions::default());
+        assert_eq!(
+            find(&matcher, "shugar mentors"),
+            [
+                ("sugar".to_string(), MatchKind::Alias),
+                ("mentos".to_string(), MatchKind::Alias)
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_word_aliases() {
+        let matcher = puzzle_matcher(MatchOptions::default());
+        let words = ["i", "love", "men", "toes", "bridge"];
+        let matches = matcher.find_matches(&words);
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].keyword, "mentos");
+        assert_eq!(matches[0].kind, MatchKind::Alias);
+        assert_eq!(matches[0].word_index, 2);
+        assert_eq!(matches[0].word_count, 2);
+        // the match covers both words, so matching carries on after them
+        assert_eq!(matches[1].keyword, "bridge");
+        assert_eq!(matches[1].word_index, 4);
+    }
+
+    #[test]
+    fn levenshtein_threshold() {
+        // "suger" is one edit from "sugar", for a similarity of exactly 0.8
+        let matcher = puzzle_matcher(MatchOptions {
+            phonetic: false,
+            ..fuzzy_options()
+        });
+        let matches = matcher.find_matches(&["suger"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].kind, MatchKind::EditDistance);
+        assert!((matches[0].similarity - 0.8).abs() < 1e-6);
+
+        let stricter = puzzle_matcher(MatchOptions {
+            min_similarity: 0.81,
+            phonetic: false,
+            ..fuzzy_options()
+        });
+        assert!(stricter.find_matches(&["suger"]).is_empty());
+
+        // exact matching is the default
+        assert!(puzzle_matcher(MatchOptions::default())
+            .find_matches(&["suger"])
+            .is_empty());
+    }
+
+    #[test]
+    fn short_words_must_match_exactly() {
+        let matcher = puzzle_matcher(MatchOptions {
+            min_similarity: 0.5,
+            ..fuzzy_options()
+        });
+        // one edit from "sugar" would do, but the word is too short to be fuzzy about
+        assert!(matcher.find_matches(&["sug"]).is_empty());
+    }
+
+    #[test]
+    fn soundex_matches() {
+        // "sagur" is two edits from "sugar", but sounds the same
+        let matcher = puzzle_matcher(fuzzy_options());
+        let matches = matcher.find_matches(&["sagur"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].keyword, "sugar");
+        assert_eq!(matches[0].kind, MatchKind::Phonetic);
+
+        // likewise "bridj", whose "j" sounds like the "g" of "bridge"
+        let matches = matcher.find_matches(&["bridj"]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].keyword, "bridge");
+        assert_eq!(matches[0].kind, MatchKind::Phonetic);
+        assert!(matches[0].similarity < fuzzy_options().min_similarity);
+
+        let without_phonetic = puzzle_matcher(MatchOptions {
+            phonetic: false,
+            ..fuzzy_options()
+        });
+        assert!(without_phonetic.find_matches(&["sagur"]).is_empty());
+    }
+
+    #[test]
+    fn soundex_codes() {
+        assert_eq!(soundex("Robert").as_deref(), Some("R163"));
+        assert_eq!(soundex("Rupert").as_deref(), Some("R163"));
+        assert_eq!(soundex("Ashcraft").as_deref(), Some("A261"));
+        assert_eq!(soundex("Tymczak").as_deref(), Some("T522"));
+        assert_eq!(soundex("Pfister").as_deref(), Some("P236"));
+        assert_eq!(soundex("sugar").as_deref(), Some("S260"));
+        assert_eq!(soundex("Lee").as_deref(), Some("L000"));
+        assert_eq!(soundex("42"), None);
+    }
+
+    #[test]
+    fn near_misses_do_not_match() {
+        let matcher = puzzle_matcher(fuzzy_options());
+        // "cigar" is two edits from "sugar" and starts with a different letter, so it neither
+        // looks nor sounds close enough
+        assert!(find(&matcher, "cigar").is_empty());
+        assert!(find(&matcher, "bring the bread").is_empty());
+        assert!(find(&matcher, "men").is_empty());
+        assert!(find(&matcher, "").is_empty());
+    }
+
+    #[test]
+    fn normalizing_words() {
+        assert_eq!(normalize("Mento's,"), "mentos");
+        assert_eq!(normalize("SUGAR!"), "sugar");
+        assert_eq!(normalize("..."), "");
+    }
+}
@@ -0,0 +1,1347 @@
+use bevy::prelude::*;
+use heron::prelude::*;
+
+mod camera;
+mod debug;
+mod hud;
+mod player;
+
+use camera::CameraPlugin;
+use debug::DebugPlugin;
+use hud::HudPlugin;
+use player::{Player, PlayerPlugin};
+
+const X_RESOLUTION: f32 = 640.0;
+pub const Y_RESOLUTION: f32 = 480.0;
+
+// z-values
+const Z_WOODEN_SIGN: f32 = 4.0;
+pub const Z_PLAYER: f32 = 3.0;
+const Z_BLUEBERRY_BASKET: f32 = 3.0;
+const Z_SUGAR_BAG: f32 = 3.0;
+const Z_WOODEN_PLANKS: f32 = 3.0;
+const Z_BEAR: f32 = 4.0;
+const Z_JAM_JAR: f32 = 5.0;
+const Z_BRIDGE: f32 = 2.0;
+const Z_LAVA_TILES: f32 = 1.0;
+
+// coordinates of objects in the jam room
+const WOODEN_SIGN_JAM_X: f32 = -400.0;
+const WOODEN_SIGN_JAM_Y: f32 = -10.0;
+const BLUEBERRY_BASKET_X: f32 = -400.0;
+const BLUEBERRY_BASKET_Y: f32 = 30.0;
+const SUGAR_BAG_INITIAL_X: f32 = -400.0;
+const SUGAR_BAG_INITIAL_Y: f32 = 70.0;
+const SUGAR_BAG_SPEED: f32 = -15.0;
+const JAM_JAR_X: f32 = -400.0;
+const JAM_JAR_Y: f32 = 30.0;
+const BEAR_X: f32 = -360.0;
+const BEAR_Y: f32 = 80.0;
+const WOODEN_PLANKS_X: f32 = -360.0;
+const WOODEN_PLANKS_Y: f32 = 70.0;
+
+// coordinates of objects in the mentos room
+const WOODEN_SIGN_MENTOS_X: f32 = 500.0;
+const WOODEN_SIGN_MENTOS_Y: f32 = 70.0;
+const COLA_X: f32 = 400.0;
+const COLA_Y: f32 = 70.0;
+const MENTOS_INITIAL_X: f32 = 400.0;
+const MENTOS_INITIAL_Y: f32 = 140.0;
+const MENTOS_SPEED: f32 = -15.0;
+const ROPE_INITIAL_X: f32 = 425.0;
+const ROPE_INITIAL_Y: f32 = 174.0;
+const ROPE_FINAL_X: f32 = 425.0;
+const ROPE_FINAL_Y: f32 = 130.0;
+const ROPE_DROP_SPEED: f32 = -60.0;
+const BULLSEYE_X: f32 = 400.0;
+const BULLSEYE_Y: f32 = 174.0;
+
+// coordinates of objects in the bridge room
+const WOODEN_SIGN_BRIDGE_X: f32 = 0.0;
+const WOODEN_SIGN_BRIDGE_Y: f32 = -10.0;
+const WOODEN_BRIDGE_X: f32 = 0.0;
+const WOODEN_BRIDGE_Y: f32 = 72.0;
+const TREASURE_CHEST_X: f32 = 0.0;
+const TREASURE_CHEST_Y: f32 = 120.0;
+
+// the lowest recognizer confidence each puzzle will accept for its keyword
+const SUGAR_MIN_CONFIDENCE: f32 = 0.5;
+const MENTOS_MIN_CONFIDENCE: f32 = 0.4;
+const BRIDGE_MIN_CONFIDENCE: f32 = 0.5;
+
+/// The words the puzzles are listening for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keyword {
+    Bridge,
+    Mentos,
+    Sugar,
+}
+
+impl Keyword {
+    /// The keyword for a word from the vocabulary asset, if any puzzle is listening for it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bridge" => Some(Keyword::Bridge),
+            "mentos" => Some(Keyword::Mentos),
+            "sugar" => Some(Keyword::Sugar),
+            _ => None,
+        }
+    }
+
+    /// The name of the keyword in the vocabulary asset.
+    pub fn name(self) -> &'static str {
+        match self {
+            Keyword::Bridge => "bridge",
+            Keyword::Mentos => "mentos",
+            Keyword::Sugar => "sugar",
+        }
+    }
+}
+
+/// Where a `SpeechEvent` came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpeechSource {
+    Voice,
+    Keyboard,
+    /// Typed in as words, for when the game can't hear the microphone.
+    Typed,
+}
+
+/// Sent whenever the player says (or types the shortcut for) a puzzle keyword. Spoken keywords are
+/// only sent once the recognizer has finalized them, so this is what the puzzles react to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeechEvent {
+    pub keyword: Keyword,
+    /// How sure the recognizer is that the keyword was said, from 0 to 1.
+    pub confidence: f32,
+    /// When the keyword started and ended, in seconds since the start of the audio stream.
+    pub start: f32,
+    pub end: f32,
+    /// Everything that was said in the utterance the keyword came from.
+    pub transcript: String,
+    pub source: SpeechSource,
+}
+
+impl SpeechEvent {
+    /// Key presses are always as confident as can be and have no place in the audio stream.
+    pub fn from_key_press(keyword: Keyword) -> Self {
+        SpeechEvent {
+            keyword,
+            confidence: 1.0,
+            start: 0.0,
+            end: 0.0,
+            transcript: String::new(),
+            source: SpeechSource::Keyboard,
+        }
+    }
+}
+
+/// Sent as soon as a keyword shows up in an interim transcript, which the recognizer may still
+/// revise. Good for giving the player early feedback, but puzzles should wait for `SpeechEvent`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TentativeSpeechEvent(pub SpeechEvent);
+
+#[cfg(feature = "speech")]
+pub mod audio_file;
+#[cfg(feature = "speech")]
+mod captions;
+// the offline keyword spotter is used instead when both are enabled
+#[cfg(feature = "deepgram")]
+pub mod deepgram;
+#[cfg(all(feature = "mock", not(target_arch = "wasm32")))]
+pub mod deepgram_mock;
+#[cfg(all(feature = "proxy", not(target_arch = "wasm32")))]
+pub mod deepgram_proxy;
+#[cfg(feature = "deepgram")]
+pub mod deepgram_transport;
+#[cfg(feature = "speech")]
+pub mod keywords;
+#[cfg(feature = "speech")]
+pub mod microphone;
+#[cfg(feature = "offline")]
+mod offline;
+#[cfg(feature = "speech")]
+mod recording;
+#[cfg(feature = "speech")]
+mod resample;
+#[cfg(feature = "speech")]
+pub mod ring_buffer;
+#[cfg(feature = "speech")]
+pub mod speech;
+#[cfg(feature = "speech")]
+mod speech_hud;
+#[cfg(feature = "speech")]
+mod speech_input;
+#[cfg(feature = "speech")]
+mod typed_input;
+#[cfg(feature = "speech")]
+mod vad;
+#[cfg(feature = "speech")]
+mod vocabulary;
+
+#[derive(PhysicsLayer)]
+enum Layer {
+    Tiles,
+    Npc,
+    Items,
+    Player,
+}
+
+#[derive(Default)]
+struct GameState {
+    mentos_puzzle_completed: bool,
+    sugar_puzzle_completed: bool,
+    jam_created: bool,
+    bridge_puzzle_completed: bool,
+    wooden_planks_collected: bool,
+    rope_coil_collected: bool,
+    bullseye_just_hit: bool,
+    treasure_chest_opened: bool,
+}
+
+/// Build the game and run it until the window closes.
+pub fn run() {
+    let mut app = App::new();
+
+    app.insert_resource(WindowDescriptor {
+        title: "Jamfest".to_string(),
+        width: X_RESOLUTION,
+        height: Y_RESOLUTION,
+        ..Default::default()
+    })
+    .insert_resource(bevy::render::texture::ImageSettings::default_nearest())
+    .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
+    .insert_resource(GameState::default())
+    .add_plugins(DefaultPlugins)
+    .add_plugin(PhysicsPlugin::default())
+    .add_plugin(PlayerPlugin)
+    .add_plugin(DebugPlugin)
+    .add_plugin(CameraPlugin)
+    .add_plugin(HudPlugin)
+    .insert_resource(Gravity::from(Vec3::new(0.0, 0.0, 0.0)))
+    .add_startup_system(spawn_wall_tiles)
+    .add_startup_system(spawn_lava_tiles)
+    .add_startup_system(spawn_blueberry_basket)
+    .add_startup_system(spawn_wooden_planks)
+    .add_startup_system(spawn_bear)
+    .add_startup_system(spawn_soda)
+    .add_startup_system(spawn_rope_coil)
+    .add_startup_system(spawn_bullseye)
+    .add_startup_system(spawn_treasure_chest)
+    .add_startup_system(spawn_wooden_signs)
+    .add_system(puzzle_sign_system)
+    .add_event::<SpeechEvent>()
+    .add_event::<TentativeSpeechEvent>()
+    .add_system(handle_sugar_said_event)
+    .add_system(handle_mentos_said_event)
+    .add_system(handle_bridge_said_event)
+    .add_system(handle_rope_coil_collected_event)
+    .add_system(explode_mentos)
+    .add_system(drop_rope)
+    .add_system(handle_wooden_planks_collected_event)
+    .add_system(move_bear_to_jam_jar)
+    .add_system(drop_sugar)
+    .add_system(check_treasure_chest_proximity);
+
+    #[cfg(feature = "speech")]
+    app.add_plugin(vocabulary::VocabularyPlugin)
+        .add_plugin(recording::RecordingPlugin)
+        .add_plugin(captions::CaptionPlugin)
+        .add_plugin(speech_hud::SpeechHudPlugin)
+        .add_plugin(speech_input::SpeechInputPlugin)
+        .add_plugin(vad::VadPlugin)
+        .add_plugin(typed_input::TypedInputPlugin);
+    #[cfg(all(feature = "deepgram", not(feature = "offline")))]
+    app.add_plugin(speech::SpeechPlugin::<deepgram_transport::DeepgramBackend>::default());
+    #[cfg(feature = "offline")]
+    app.add_plugin(speech::SpeechPlugin::<offline::OfflineBackend>::default());
+
+    app.run();
+}
+
+#[derive(Component)]
+pub(crate) struct BlueberryBasket;
+
+fn spawn_blueberry_basket(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("blueberry_basket.png"),
+            transform: Transform::from_xyz(
+                BLUEBERRY_BASKET_X,
+                BLUEBERRY_BASKET_Y,
+                Z_BLUEBERRY_BASKET,
+            ),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(BlueberryBasket);
+}
+
+#[derive(Component)]
+pub(crate) struct WoodenPlanks;
+
+fn spawn_wooden_planks(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("wooden_planks.png"),
+            transform: Transform::from_xyz(WOODEN_PLANKS_X, WOODEN_PLANKS_Y, Z_WOODEN_PLANKS),
+            ..default()
+        })
+        .insert(RigidBody::Sensor)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(WoodenPlanks);
+}
+
+#[derive(Component)]
+pub(crate) struct RopeCoil;
+
+fn spawn_rope_coil(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("rope_coil.png"),
+            transform: Transform::from_xyz(ROPE_INITIAL_X, ROPE_INITIAL_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::KinematicVelocityBased)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 16.0, 1.0),
+            border_radius: None,
+        })
+        .insert(Velocity::from_linear(Vec3::ZERO))
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(RopeCoil);
+}
+
+#[derive(Component)]
+pub(crate) struct Bullseye;
+
+fn spawn_bullseye(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("bullseye.png"),
+            transform: Transform::from_xyz(BULLSEYE_X, BULLSEYE_Y, 2.0),
+            ..default()
+        })
+        .insert(RigidBody::Sensor)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Items),
+        )
+        .insert(Bullseye);
+}
+
+#[derive(Component)]
+pub(crate) struct TreasureChest;
+
+fn spawn_treasure_chest(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("treasure_chest_closed.png"),
+            transform: Transform::from_xyz(TREASURE_CHEST_X, TREASURE_CHEST_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(TreasureChest);
+}
+
+fn spawn_opened_treasure_chest(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("treasure_chest_opened.png"),
+            transform: Transform::from_xyz(TREASURE_CHEST_X, TREASURE_CHEST_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(TreasureChest);
+}
+
+#[derive(Component)]
+pub(crate) struct Soda;
+
+fn spawn_soda(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("soda_bottle.png"),
+            transform: Transform::from_xyz(COLA_X, COLA_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 20.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(Soda);
+}
+
+fn despawn_soda(
+    commands: &mut Commands,
+    soda_bottle_query: Query<(Entity, &Transform), With<Soda>>,
+) {
+    let (soda_bottle_entity, _soda_bottle_transform) = soda_bottle_query.single();
+    commands.entity(soda_bottle_entity).despawn_recursive();
+}
+
+#[derive(Component)]
+pub(crate) struct EmptySoda;
+
+fn spawn_empty_soda(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("soda_bottle_empty.png"),
+            transform: Transform::from_xyz(COLA_X, COLA_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 20.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(EmptySoda);
+}
+
+#[derive(Component)]
+pub(crate) struct Mentos;
+
+fn spawn_mentos(mut commands: Commands, asset_server: Res<AssetServer>) {
+    info!("Spawning mentos.");
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("mint_package.png"),
+            transform: Transform::from_xyz(MENTOS_INITIAL_X, MENTOS_INITIAL_Y, 1.0),
+            ..default()
+        })
+        .insert(RigidBody::KinematicVelocityBased)
+        .insert(Velocity::from_linear(Vec3::ZERO))
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(Mentos);
+}
+
+#[derive(Component)]
+pub(crate) struct WoodenSign(&'static str);
+
+fn spawn_wooden_signs(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_wooden_sign(
+        &mut commands,
+        &asset_server,
+        Transform::from_xyz(WOODEN_SIGN_JAM_X, WOODEN_SIGN_JAM_Y, Z_WOODEN_SIGN),
+        "The bear is hungry and would like some jam. Here are some blueberries, what else do you need to make jam?",
+    );
+
+    spawn_wooden_sign(
+        &mut commands,
+        &asset_server,
+        Transform::from_xyz(WOODEN_SIGN_MENTOS_X, WOODEN_SIGN_MENTOS_Y, Z_WOODEN_SIGN),
+        "Pop the bottle cap to hit the bullseye. What could you mix with the soda to do this?",
+    );
+
+    spawn_wooden_sign(
+        &mut commands,
+        &asset_server,
+        Transform::from_xyz(WOODEN_SIGN_BRIDGE_X, WOODEN_SIGN_BRIDGE_Y, Z_WOODEN_SIGN),
+        "To get the treasure, find some rope and some wood. Then tell me what you can make with them to cross the lava.",
+    );
+}
+
+fn spawn_wooden_sign(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    position: Transform,
+    text: &'static str,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("wooden_sign.png"),
+            transform: position,
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(3.0, 16.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(WoodenSign(text));
+}
+
+fn spawn_wall_tiles(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut coordinates = Vec::new();
+    // bridge room
+    for x in -10..=10 {
+        for y in 10..=11 {
+            coordinates.push((x, y));
+        }
+        for y in -9..=-8 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in -10..=-9 {
+        for y in -9..=-3 {
+            coordinates.push((x, y));
+        }
+        for y in 3..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in 9..=10 {
+        for y in -9..=-3 {
+            coordinates.push((x, y));
+        }
+        for y in 3..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    // jam room
+    for x in -10 - 25..=10 - 25 {
+        for y in 10..=11 {
+            coordinates.push((x, y));
+        }
+        for y in -9..=-8 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in -10 - 25..=-9 - 25 {
+        for y in -9..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in 9 - 25..=10 - 25 {
+        for y in -9..=-3 {
+            coordinates.push((x, y));
+        }
+        for y in 3..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    // mentos room
+    for x in -10 + 25..=10 + 25 {
+        for y in 10..=11 {
+            coordinates.push((x, y));
+        }
+        for y in -9..=-8 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in -10 + 25..=-9 + 25 {
+        for y in -9..=-3 {
+            coordinates.push((x, y));
+        }
+        for y in 3..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in 9 + 25..=10 + 25 {
+        for y in -9..=11 {
+            coordinates.push((x, y));
+        }
+    }
+
+    // hallways
+    for x in -15..=-10 {
+        for y in 3..=4 {
+            coordinates.push((x, y));
+        }
+        for y in -4..=-3 {
+            coordinates.push((x, y));
+        }
+    }
+    for x in -15..=-10 {
+        for y in 3..=4 {
+            coordinates.push((x, y));
+        }
+        for y in -4..=-3 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in 10..=15 {
+        for y in 3..=4 {
+            coordinates.push((x, y));
+        }
+        for y in -4..=-3 {
+            coordinates.push((x, y));
+        }
+    }
+    for x in 10..=15 {
+        for y in 3..=4 {
+            coordinates.push((x, y));
+        }
+        for y in -4..=-3 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for coordinate in coordinates {
+        spawn_wall_tile(
+            &mut commands,
+            &asset_server,
+            Transform::from_xyz(
+                coordinate.0 as f32 * 16.0,
+                coordinate.1 as f32 * 16.0,
+                Z_LAVA_TILES,
+            ),
+        );
+    }
+}
+
+fn spawn_wall_tile(commands: &mut Commands, asset_server: &Res<AssetServer>, position: Transform) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("wall_tile.png"),
+            transform: position,
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Tiles)
+                .with_mask(Layer::Player),
+        );
+}
+
+// We will use this as a hack to keep track of which lava tiles we will replace with
+// non-collidable lava tiles. This is how we will simulate walking over the bridge
+// when it appears. There is certainly a better way to do this.
+#[derive(Component)]
+pub(crate) struct LavaTileTracked;
+
+fn spawn_lava_tiles(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // non-tracked tiles
+    let mut coordinates = Vec::new();
+    for x in -8..=-2 {
+        for y in 4..=5 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for x in 2..=8 {
+        for y in 4..=5 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for coordinate in coordinates {
+        spawn_lava_tile(
+            &mut commands,
+            &asset_server,
+            Transform::from_xyz(
+                coordinate.0 as f32 * 16.0,
+                coordinate.1 as f32 * 16.0,
+                Z_LAVA_TILES,
+            ),
+        );
+    }
+
+    // tracked tiles
+    let mut coordinates = Vec::new();
+    for x in -1..=1 {
+        for y in 4..=5 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for coordinate in coordinates {
+        spawn_lava_tile_tracked(
+            &mut commands,
+            &asset_server,
+            Transform::from_xyz(
+                coordinate.0 as f32 * 16.0,
+                coordinate.1 as f32 * 16.0,
+                Z_LAVA_TILES,
+            ),
+        );
+    }
+}
+
+fn spawn_lava_tile(commands: &mut Commands, asset_server: &Res<AssetServer>, position: Transform) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("lava_tile.png"),
+            transform: position,
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Tiles)
+                .with_mask(Layer::Player),
+        );
+}
+
+fn spawn_lava_tile_tracked(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    position: Transform,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("lava_tile.png"),
+            transform: position,
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Tiles)
+                .with_mask(Layer::Player),
+        )
+        .insert(LavaTileTracked);
+}
+
+fn spawn_lava_tiles_non_collidable(commands: &mut Commands, asset_server: &Res<AssetServer>) {
+    let mut coordinates = Vec::new();
+    for x in -1..=1 {
+        for y in 4..=5 {
+            coordinates.push((x, y));
+        }
+    }
+
+    for coordinate in coordinates {
+        spawn_lava_tile_non_collidable(
+            commands,
+            asset_server,
+            Transform::from_xyz(
+                coordinate.0 as f32 * 16.0,
+                coordinate.1 as f32 * 16.0,
+                Z_LAVA_TILES,
+            ),
+        );
+    }
+}
+
+fn spawn_lava_tile_non_collidable(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    position: Transform,
+) {
+    commands.spawn_bundle(SpriteBundle {
+        texture: asset_server.load("lava_tile.png"),
+        transform: position,
+        ..default()
+    });
+}
+
+#[derive(Component)]
+pub(crate) struct SugarBag;
+
+fn spawn_sugar_bag(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("sugar_bag.png"),
+            transform: Transform::from_xyz(SUGAR_BAG_INITIAL_X, SUGAR_BAG_INITIAL_Y, Z_SUGAR_BAG),
+            ..default()
+        })
+        .insert(RigidBody::KinematicVelocityBased)
+        .insert(Velocity::from_linear(Vec3::ZERO))
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(SugarBag);
+}
+
+#[derive(Component)]
+pub(crate) struct JamJar;
+
+fn spawn_jam_jar(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("jam_jar.png"),
+            transform: Transform::from_xyz(JAM_JAR_X, JAM_JAR_Y, Z_JAM_JAR),
+            ..default()
+        })
+        .insert(RigidBody::Static)
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(8.0, 8.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Items)
+                .with_mask(Layer::Player),
+        )
+        .insert(JamJar);
+}
+
+#[derive(Component)]
+pub(crate) struct WoodenBridge;
+
+fn spawn_wooden_bridge(commands: &mut Commands, asset_server: &Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("wooden_bridge.png"),
+            transform: Transform::from_xyz(WOODEN_BRIDGE_X, WOODEN_BRIDGE_Y, Z_BRIDGE),
+            ..default()
+        })
+        .insert(WoodenBridge);
+}
+
+#[derive(Component)]
+pub(crate) struct Bear;
+
+fn spawn_bear(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load("bear_npc_1.png"),
+            transform: Transform::from_xyz(BEAR_X, BEAR_Y, Z_BEAR),
+            ..default()
+        })
+        .insert(RigidBody::Dynamic)
+        .insert(Velocity::from_linear(Vec3::ZERO))
+        .insert(CollisionShape::Cuboid {
+            half_extends: Vec3::new(12.0, 16.0, 1.0),
+            border_radius: None,
+        })
+        .insert(
+            CollisionLayers::none()
+                .with_group(Layer::Npc)
+                .with_mask(Layer::Player),
+        )
+        // giving the bear infinite mass so that you can't push it
+        .insert(PhysicMaterial {
+            friction: 1.0,
+            density: f32::MAX,
+            ..Default::default()
+        })
+        .insert(Bear);
+}
+
+/// Whether any of this frame's speech events is a confident enough utterance of `keyword`.
+fn keyword_said(
+    speech_events: &mut EventReader<SpeechEvent>,
+    keyword: Keyword,
+    min_confidence: f32,
+) -> bool {
+    speech_events
+        .iter()
+        .filter(|event| event.keyword == keyword)
+        .any(|event| {
+            if event.confidence < min_confidence {
+                info!(
+                    "Ignoring {:?} said with confidence {} in {:?}.",
+                    keyword, event.confidence, event.transcript
+                );
+            }
+            event.confidence >= min_confidence
+        })
+}
+
+fn handle_bridge_said_event(
+    mut speech_events: EventReader<SpeechEvent>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut game_state: ResMut<GameState>,
+    player_query: Query<&Transform, With<Player>>,
+    treasure_chest_query: Query<&Transform, With<TreasureChest>>,
+    lava_tile_tracked_query: Query<Entity, With<LavaTileTracked>>,
+) {
+    if game_state.bridge_puzzle_completed
+        || !game_state.rope_coil_collected
+        || !game_state.wooden_planks_collected
+    {
+        return;
+    }
+
+    let player_transform = player_query.single();
+    let treasure_chest_transform = treasure_chest_query.single();
+
+    if player_transform
+        .translation
+        .distance(treasure_chest_transform.translation)
+        < 200.0
+    {
+        let bridge_said = keyword_said(&mut speech_events, Keyword::Bridge, BRIDGE_MIN_CONFIDENCE);
+        if bridge_said {
+            info!("You said bridge!");
+            spawn_wooden_bridge(&mut commands, &asset_server);
+            despawn_lava_tiles_tracked(&mut commands, lava_tile_tracked_query);
+            spawn_lava_tiles_non_collidable(&mut commands, &asset_server);
+            game_state.bridge_puzzle_completed = true;
+        }
+        speech_events.clear();
+    }
+}
+
+fn despawn_lava_tiles_tracked(
+    commands: &mut Commands,
+    lava_tile_tracked_query: Query<Entity, With<LavaTileTracked>>,
+) {
+    for lava_tile in lava_tile_tracked_query.iter() {
+        info!("Despawning a tracked lava tile!");
+        commands.entity(lava_tile).despawn_recursive();
+    }
+}
+
+fn check_treasure_chest_proximity(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    player_query: Query<&Transform, With<Player>>,
+    treasure_chest_query: Query<(Entity, &Transform), With<TreasureChest>>,
+    mut game_state: ResMut<GameState>,
+) {
+    if game_state.treasure_chest_opened {
+        return;
+    }
+
+    let player_transform = player_query.single();
+    let (_, treasure_chest_transform) = treasure_chest_query.single();
+
+    if player_transform
+        .translation
+        .distance(treasure_chest_transform.translation)
+        < 40.0
+    {
+        despawn_treasure_chest(&mut commands, treasure_chest_query);
+        spawn_win_text(&mut commands, &asset_server);
+        spawn_opened_treasure_chest(commands, asset_server);
+        game_state.treasure_chest_opened = true;
+    }
+}
+
+fn despawn_treasure_chest(
+    commands: &mut Commands,
+    treasure_chest_query: Query<(Entity, &Transform), With<TreasureChest>>,
+) {
+    let (treasure_chest_entity, _) = treasure_chest_query.single();
+    commands.entity(treasure_chest_entity).despawn_recursive();
+}
+
+fn handle_sugar_said_event(
+    mut speech_events: EventReader<SpeechEvent>,
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut game_state: ResMut<GameState>,
+    player_query: Query<&Transform, With<Player>>,
+    blueberry_basket_query: Query<&Transform, With<BlueberryBasket>>,
+) {
+    if game_state.sugar_puzzle_completed {
+        return;
+    }
+
+    let player_transform = player_query.single();
+    let blueberry_basket_transform = blueberry_basket_query.single();
+
+    if player_transform
+        .translation
+        .distance(blueberry_basket_transform.translation)
+        < 200.0
+    {
+        let sugar_said = keyword_said(&mut speech_events, Keyword::Sugar, SUGAR_MIN_CONFIDENCE);
+        if sugar_said {
+            info!("You said sugar!");
+            spawn_sugar_bag(commands, asset_server);
+            game_state.sugar_puzzle_completed = true;
+        }
+        speech_events.clear();
+    }
+}
+
+fn handle_mentos_said_event(
+    mut speech_events: EventReader<SpeechEvent>,
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut game_state: ResMut<GameState>,
+    player_query: Query<&Transform, With<Player>>,
+    soda_query: Query<&Transform, With<Soda>>,
+) {
+    if game_state.mentos_puzzle_completed {
+        return;
+    }
+
+    let player_transform = player_query.single();
+    let soda_transform = soda_query.single();
+
+    if player_transform
+        .translation
+        .distance(soda_transform.translation)
+        < 200.0
+    {
+        let mentos_said = keyword_said(&mut speech_events, Keyword::Mentos, MENTOS_MIN_CONFIDENCE);
+        if mentos_said {
+            info!("You said mentos!");
+            spawn_mentos(commands, asset_server);
+            game_state.mentos_puzzle_completed = true;
+        }
+        speech_events.clear();
+    }
+}
+
+fn explode_mentos(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut game_state: ResMut<GameState>,
+    mut mentos_query: Query<(Entity, &Transform, &mut Velocity), With<Mentos>>,
+    soda_query: Query<(Entity, &Transform), With<Soda>>,
+) {
+    if let Ok((mentos, mentos_transform, mut mentos_velocity)) = mentos_query.get_single_mut() {
+        let (_soda_entity, soda_transform) = soda_query.single();
+        let difference = mentos_transform.translation - soda_transform.translation;
+        let distance = difference.length();
+        if distance < 20.0 {
+            commands.entity(mentos).despawn_recursive();
+            info!("Setting bullseye_just_hit to true.");
+            game_state.bullseye_just_hit = true;
+            despawn_soda(&mut commands, soda_query);
+            spawn_empty_soda(commands, asset_server);
+        } else {
+            let new_velocity = difference.normalize() * MENTOS_SPEED;
+            *mentos_velocity = Velocity::from_linear(new_velocity);
+        }
+    }
+}
+
+fn drop_rope(
+    mut game_state: ResMut<GameState>,
+    mut rope_coil_query: Query<(&Transform, &mut Velocity), With<RopeCoil>>,
+) {
+    if !game_state.bullseye_just_hit {
+        return;
+    }
+
+    if let Ok((rope_coil_transform, mut rope_coil_velocity)) = rope_coil_query.get_single_mut() {
+        let difference =
+            rope_coil_transform.translation - Vec3::new(ROPE_FINAL_X, ROPE_FINAL_Y, 1.0);
+        let distance = difference.length();
+        if distance < 5.0 {
+            *rope_coil_velocity = Velocity::from_linear(Vec3::ZERO);
+            game_state.bullseye_just_hit = false;
+        } else {
+            let new_velocity = difference.normalize() * ROPE_DROP_SPEED;
+            *rope_coil_velocity = Velocity::from_linear(new_velocity);
+        }
+    }
+}
+
+fn drop_sugar(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut game_state: ResMut<GameState>,
+    mut sugar_bag_query: Query<(Entity, &Transform, &mut Velocity), With<SugarBag>>,
+    mut blueberry_basket_query: Query<(Entity, &Transform), With<BlueberryBasket>>,
+) {
+    if !game_state.sugar_puzzle_completed || game_state.jam_created {
+        return;
+    }
+
+    if let Ok((_, sugar_bag_transform, mut sugar_bag_velocity)) = sugar_bag_query.get_single_mut() {
+        if let Ok((_, blueberry_basket_transform)) = blueberry_basket_query.get_single_mut() {
+            let difference =
+                sugar_bag_transform.translation - blueberry_basket_transform.translation;
+            let distance = difference.length();
+            if distance < 5.0 {
+                despawn_sugar_bag(&mut commands, sugar_bag_query);
+                despawn_blueberry_basket(&mut commands, blueberry_basket_query);
+                spawn_jam_jar(&mut commands, asset_server);
+                game_state.jam_created = true;
+            } else {
+                let new_velocity = difference.normalize() * SUGAR_BAG_SPEED;
+                *sugar_bag_velocity = Velocity::from_linear(new_velocity);
+            }
+        }
+    }
+}
+
+fn move_bear_to_jam_jar(
+    jam_jar_query: Query<&Transform, With<JamJar>>,
+    mut bear_query: Query<(&Transform, &mut Velocity), With<Bear>>,
+) {
+    if let Ok(jam_jar) = jam_jar_query.get_single() {
+        let (bear_transform, mut bear_velocity) = bear_query.single_mut();
+        let difference = jam_jar.translation - bear_transform.translation;
+        let distance = difference.length();
+        if distance > 20.0 {
+            let direction = difference.normalize();
+            *bear_velocity = Velocity::from_linear(direction * 10.0);
+        } else {
+            // Also start bear eating jam animation and allow user to pick up wooden planks.
+            // That or make the bear collision box really big or something :shrug:
+            // Actually, should allow picking up the wood as soon as the bear has cleared the wood.
+            *bear_velocity = Velocity::from_linear(Vec3::ZERO);
+        }
+    }
+}
+
+fn despawn_sugar_bag(
+    commands: &mut Commands,
+    sugar_bag_query: Query<(Entity, &Transform, &mut Velocity), With<SugarBag>>,
+) {
+    let (sugar_bag_entity, _, _) = sugar_bag_query.single();
+    commands.entity(sugar_bag_entity).despawn_recursive();
+}
+
+fn despawn_blueberry_basket(
+    commands: &mut Commands,
+    blueberry_basket_query: Query<(Entity, &Transform), With<BlueberryBasket>>,
+) {
+    let (blueberry_basket_entity, _) = blueberry_basket_query.single();
+    commands.entity(blueberry_basket_entity).despawn_recursive();
+}
+
+fn puzzle_sign_system(
+    mut commands: Commands,
+    player_query: Query<&Transform, With<Player>>,
+    wooden_sign_query: Query<(&WoodenSign, &Transform)>,
+    puzzle_text_query: Query<Entity, With<PuzzleText>>,
+    asset_server: Res<AssetServer>,
+) {
+    let player_transform = player_query.single();
+
+    let mut found_wooden_sign = None;
+
+    for (wooden_sign, wooden_sign_transform) in wooden_sign_query.iter() {
+        if player_transform
+            .translation
+            .distance(wooden_sign_transform.translation)
+            < 40.0
+        {
+            found_wooden_sign = Some(wooden_sign);
+        }
+    }
+
+    match found_wooden_sign {
+        Some(wooden_sign) => {
+            if puzzle_text_query.is_empty() {
+                spawn_puzzle_text(&mut commands, asset_server, wooden_sign.0);
+            }
+        }
+        None => {
+            despawn_puzzle_text(&mut commands, puzzle_text_query);
+        }
+    }
+}
+
+fn despawn_puzzle_text(
+    commands: &mut Commands,
+    puzzle_text_query: Query<Entity, With<PuzzleText>>,
+) {
+    for puzzle_text_entity in puzzle_text_query.iter() {
+        commands.entity(puzzle_text_entity).despawn_recursive();
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct PuzzleText;
+
+fn spawn_puzzle_text(commands: &mut Commands, asset_server: Res<AssetServer>, text: &'static str) {
+    let entity = commands.spawn().id();
+
+    // the first bundle we insert is just a background color
+    // but somehow it "inherits"(?) the style from the subsequently
+    // inserted text object - note that any style given to the
+    // background color will be ignored
+    // however, if one inserts the background color after the
+    // text object, the text object's style will be ignored
+    // this is all very annoying, as those components, not
+    // being children of a mutual parent with a style or anything,
+    // should be independent, unless Bevy is doing something
+    // on the backend that is not documented
+    commands
+        .entity(entity)
+        .insert_bundle(NodeBundle {
+            color: Color::rgba(0.0, 0.0, 0.0, 0.90).into(),
+            ..default()
+        })
+        .insert_bundle(
+            TextBundle::from_sections([TextSection::new(
+                text,
+                TextStyle {
+                    font: asset_server.load("kongtext.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            )])
+            // centering the text in the way we'd like was quite difficult
+            // and might be worth more thought
+            .with_style(Style {
+                align_self: AlignSelf::FlexStart,
+                justify_content: JustifyContent::Center,
+                max_size: Size {
+                    width: Val::Px(X_RESOLUTION),
+                    height: Val::Auto,
+                },
+                margin: UiRect {
+                    left: Val::Auto,
+                    right: Val::Auto,
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(PuzzleText);
+}
+
+fn spawn_win_text(commands: &mut Commands, asset_server: &Res<AssetServer>) {
+    commands.spawn_bundle(
+        TextBundle::from_sections([TextSection::new(
+            "YOU WIN!",
+            TextStyle {
+                font: asset_server.load("kongtext.ttf"),
+                font_size: 32.0,
+                color: Color::WHITE,
+            },
+        )])
+        // centering the text in the way we'd like was quite difficult
+        // and might be worth more thought
+        .with_style(Style {
+            align_self: AlignSelf::FlexStart,
+            justify_content: JustifyContent::Center,
+
+            margin: UiRect {
+                left: Val::Auto,
+                right: Val::Auto,
+                top: Val::Auto,
+                bottom: Val::Auto,
+            },
+
+            ..default()
+        }),
+    );
+}
+
+fn handle_rope_coil_collected_event(
+    mut commands: Commands,
+    mut events: EventReader<CollisionEvent>,
+    mut game_state: ResMut<GameState>,
+    rope_coil_query: Query<Entity, With<RopeCoil>>,
+    player_query: Query<Entity, With<Player>>,
+) {
+    let player = player_query.single();
+
+    if let Ok(rope_coil) = rope_coil_query.get_single() {
+        for event in events.iter() {
+            let (e1, e2) = event.rigid_body_entities();
+            if (e1 == rope_coil || e2 == rope_coil) && (e1 == player || e2 == player) {
+                game_state.rope_coil_collected = true;
+                commands.entity(rope_coil).despawn_recursive();
+                info!("Collected rope coil!");
+                break;
+            }
+        }
+    }
+}
+
+fn handle_wooden_planks_collected_event(
+    mut commands: Commands,
+    mut events: EventReader<CollisionEvent>,
+    mut game_state: ResMut<GameState>,
+    wooden_planks_query: Query<(Entity, &Transform), With<WoodenPlanks>>,
+    player_query: Query<Entity, With<Player>>,
+    bear_query: Query<&Transform, With<Bear>>,
+) {
+    let player_entity = player_query.single();
+    let bear_transform = bear_query.single();
+
+    if let Ok((wooden_planks_entity, wooden_planks_transform)) = wooden_planks_query.get_single() {
+        // this feels a little bit hacky, but essentially, we don't want
+        // the player to be able to pick up the wood if the bear is too close to the wood
+        if wooden_planks_transform
+            .translation
+            .distance(bear_transform.translation)
+            < 16.0
+        {
+            return;
+        }
+
+        for event in events.iter() {
+            let (e1, e2) = event.rigid_body_entities();
+            if (e1 == wooden_planks_entity || e2 == wooden_planks_entity)
+                && (e1 == player_entity || e2 == player_entity)
+            {
+                game_state.wooden_planks_collected = true;
+                commands.entity(wooden_planks_entity).despawn_recursive();
+                info!("Collected wooden planks!");
+                break;
+            }
+        }
+    }
+}
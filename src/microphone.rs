@@ -1,84 +1,191 @@
-use super::SpeechEvent;
+//! Recording from the microphone, as an `AudioSource` for any speech backend.
+use super::audio_file::{self, AudioFile};
+use super::resample::Resampler;
+use super::ring_buffer::RingBuffer;
+use super::speech::{AudioBuffer, AudioSource, QueueCounts, SpeechLatencySettings};
 use bevy::prelude::*;
 
-use fon::{mono::Mono32, Audio, Frame};
+use fon::{mono::Mono32, Audio, Frame, Stream};
 use pasts::exec;
-use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
 use wavy::{Microphone, MicrophoneStream};
-use web_sys::{MessageEvent, WebSocket};
-
-/// When DG sends us ASR transcripts we'll receive them asynchronously on the `WebSocket`. Those
-/// are then processed and speech events are potentially sent to this receiver that is stored as a
-/// global resource. Systems can then use this resource to consume those messages.
-struct SpeechEventReceiver(crossbeam_channel::Receiver<SpeechEvent>);
-
-pub struct MicrophonePlugin;
-
-impl Plugin for MicrophonePlugin {
-    fn build(&self, app: &mut App) {
-        app.init_resource::<MicrophoneReceiver>()
-            .add_startup_system(connect_to_deepgram.exclusive_system())
-            .add_startup_system(setup_asr_handler)
-            .add_system(proxy_speech_events)
-            .add_system(proxy_audio_to_deepgram);
+
+/// If the browser or OS won't tell us the microphone's sample rate, we have to guess. This is the
+/// most common rate, but the transcripts will suffer if the guess is wrong.
+const FALLBACK_SAMPLE_RATE: u32 = 44_100;
+
+/// Speech recognition works just as well at 16 kHz as at the 44.1 or 48 kHz most microphones
+/// record at, and it takes roughly a third of the bandwidth to send.
+const DEFAULT_TARGET_SAMPLE_RATE: u32 = 16_000;
+
+/// The microphone hands us audio in whatever size it likes, often only a few milliseconds at a
+/// time, so we collect it into buffers of this length. Deepgram suggests 20 to 100 ms.
+const DEFAULT_CHUNK_SECONDS: f32 = 0.05;
+
+/// How many buffers the game can hand back to be filled again. It reads everything waiting each
+/// frame and hands it straight back, so only a frame's worth are ever spare at once.
+const MAX_SPARE_CHUNKS: usize = 16;
+
+/// How the microphone audio is prepared before it is sent. Insert this resource before adding the
+/// `SpeechPlugin` to change it, since it is read when the microphone is connected.
+#[derive(Clone, Debug)]
+pub struct MicrophoneSettings {
+    /// Resample the microphone audio to this rate, or send it at the microphone's own rate if
+    /// this is `None`.
+    pub target_sample_rate: Option<u32>,
+    /// How long each buffer of audio is. Shorter buffers reach the transport sooner, and longer
+    /// ones make for fewer messages.
+    pub chunk_seconds: f32,
+    /// Play this recording instead of listening to the microphone. By default this is whatever
+    /// `JAMFEST_AUDIO_FILE` names, if anything.
+    pub audio_file: Option<AudioFile>,
+}
+
+impl Default for MicrophoneSettings {
+    fn default() -> Self {
+        MicrophoneSettings {
+            target_sample_rate: Some(DEFAULT_TARGET_SAMPLE_RATE),
+            chunk_seconds: DEFAULT_CHUNK_SECONDS,
+            audio_file: AudioFile::from_env(),
+        }
     }
 }
 
-/// We will have one handle for the microphone as a global resource.
-struct MicrophoneReceiver {
-    rx: crossbeam_channel::Receiver<Vec<i16>>,
+/// Audio recorded by the microphone (or played from a file), which is resampled and converted on
+/// another thread (or the browser's event loop) and handed over through a queue.
+pub struct MicrophoneSource {
+    queue: RingBuffer<AudioBuffer>,
+    /// Buffers the game has finished with, for the microphone to fill again.
+    spares: RingBuffer<Vec<i16>>,
+    /// A recording stops when it's finished, which isn't a problem.
+    playing_file: bool,
 }
 
-impl FromWorld for MicrophoneReceiver {
-    fn from_world(_world: &mut World) -> Self {
-        let (audio_sender, audio_receiver) = crossbeam_channel::unbounded();
+impl FromWorld for MicrophoneSource {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world
+            .get_resource_or_insert_with(MicrophoneSettings::default)
+            .clone();
+        let max_latency_seconds = world
+            .get_resource_or_insert_with(SpeechLatencySettings::default)
+            .max_source_latency_seconds;
+        let queue = RingBuffer::new(max_latency_seconds, AudioBuffer::duration);
+        let spares = RingBuffer::with_length(MAX_SPARE_CHUNKS);
+        let chunker = Chunker::new(settings.chunk_seconds, queue.clone(), spares.clone());
+
+        let playing_file = settings.audio_file.is_some();
+        match settings.audio_file.clone() {
+            Some(file) => audio_file::play(file, settings.target_sample_rate, chunker),
+            None => {
+                connect_to_microphone(settings, chunker);
+                info!("Connected to microphone.");
+            }
+        }
 
-        connect_to_microphone(audio_sender);
+        MicrophoneSource {
+            queue,
+            spares,
+            playing_file,
+        }
+    }
+}
 
-        info!("Connected to microphone.");
+impl AudioSource for MicrophoneSource {
+    fn try_read(&mut self) -> Option<AudioBuffer> {
+        self.queue.pop()
+    }
 
-        MicrophoneReceiver { rx: audio_receiver }
+    fn counts(&self) -> QueueCounts {
+        self.queue.counts()
     }
-}
 
-/// We are using a non-send resource to handle the websocket client.
-/// See more here: https://bevy-cheatbook.github.io/programming/non-send.html
-/// We are also temporarily using a proxy websocket server to handle credentials.
-fn connect_to_deepgram(world: &mut World) {
-    let credentials = std::env!("DEEPGRAM_API_KEY");
-    let protocol = vec!["Token", credentials];
-    let client = WebSocket::new_with_str_sequence(
-        "wss://api.deepgram.com/v1/listen?encoding=linear16&sample_rate=44100&channels=1",
-        &serde_wasm_bindgen::to_value(&protocol).unwrap(),
-    )
-    .unwrap();
+    fn is_closed(&self) -> bool {
+        !self.playing_file && self.queue.is_abandoned()
+    }
 
-    info!("Connected to Deepgram. Probably.");
+    fn recycle(&mut self, audio: AudioBuffer) {
+        self.spares.push(audio.samples);
+    }
+}
 
-    world.insert_non_send_resource(DeepgramWebsocket {
-        client: Some(client),
-    });
+/// Collects audio into buffers `chunk_seconds` long for the game to read, filling buffers the
+/// game has handed back rather than allocating new ones.
+pub struct Chunker {
+    chunk_seconds: f32,
+    /// The buffer being filled, which is sent once it's `chunk_seconds` long.
+    chunk: Vec<i16>,
+    /// Where the audio goes, for the game to read it from.
+    queue: RingBuffer<AudioBuffer>,
+    /// Where the game hands buffers back.
+    spares: RingBuffer<Vec<i16>>,
 }
 
-/// This will be a non-send resource, which is perfect for polling clients
-/// which poll in a bevy system which occurs once per frame ish.
-#[derive(Default)]
-struct DeepgramWebsocket {
-    client: Option<WebSocket>,
+impl Chunker {
+    pub fn new(
+        chunk_seconds: f32,
+        queue: RingBuffer<AudioBuffer>,
+        spares: RingBuffer<Vec<i16>>,
+    ) -> Self {
+        Chunker {
+            chunk_seconds,
+            chunk: Vec::new(),
+            queue,
+            spares,
+        }
+    }
+
+    /// Convert `samples` to linear16 and add them to the chunk being filled, sending it whenever
+    /// it's full. Whatever's left over waits for the next call.
+    pub fn push(&mut self, sample_rate: u32, samples: &[f32]) {
+        let chunk_length = ((sample_rate as f32 * self.chunk_seconds) as usize).max(1);
+        for &sample in samples {
+            self.chunk.push(f32_to_i16(sample));
+            if self.chunk.len() >= chunk_length {
+                let spare = match self.spares.pop() {
+                    Some(mut spare) => {
+                        spare.clear();
+                        spare
+                    }
+                    None => Vec::with_capacity(chunk_length),
+                };
+                let samples = std::mem::replace(&mut self.chunk, spare);
+                self.queue.push(AudioBuffer {
+                    sample_rate,
+                    samples,
+                });
+            }
+        }
+    }
+
+    /// Throw away the chunk being filled, e.g. because the rest of it would be at another rate.
+    pub fn clear(&mut self) {
+        self.chunk.clear();
+    }
+
+    /// Whether the game has stopped reading the audio.
+    pub fn is_abandoned(&self) -> bool {
+        self.queue.is_abandoned()
+    }
 }
 
 /// This is based on the following example: https://github.com/libcala/wavy/blob/stable/examples/record/src/main.rs
-fn connect_to_microphone(tx: crossbeam_channel::Sender<Vec<i16>>) {
-    let mut state = State {
-        buffer: Audio::with_silence(44_100, 0),
-        tx,
-    };
-    let mut microphone = Microphone::default();
-
-    exec!(state.event(pasts::wait! {
-        Event::Record(microphone.record().await),
-    }))
+fn connect_to_microphone(settings: MicrophoneSettings, chunker: Chunker) {
+    run_in_background(move || {
+        let mut state = State {
+            buffer: Audio::with_silence(FALLBACK_SAMPLE_RATE, 0),
+            warned_about_sample_rate: false,
+            target_sample_rate: settings.target_sample_rate,
+            resampler: None,
+            samples: Vec::new(),
+            resampled: Vec::new(),
+            chunker,
+        };
+        state.set_sample_rate(FALLBACK_SAMPLE_RATE.into());
+        let mut microphone = Microphone::default();
+
+        exec!(state.event(pasts::wait! {
+            Event::Record(microphone.record().await),
+        }))
+    });
 }
 
 /// An event handled by some microphone event loop.
@@ -89,41 +196,91 @@ enum Event<'a> {
 
 /// A state for handling the microphone audio stream.
 struct State {
-    /// Temporary buffer for holding real-time audio samples.
+    /// Temporary buffer for holding real-time audio samples. Its sample rate always matches the
+    /// microphone's, so that `fon` doesn't resample the audio when we extend it.
     buffer: Audio<Mono32>,
-    /// The sending half of a channel, used to send the audio to another system.
-    tx: crossbeam_channel::Sender<Vec<i16>>,
+    /// We only want to warn once if we can't tell the microphone's sample rate.
+    warned_about_sample_rate: bool,
+    /// The rate to send audio at, if not the microphone's own.
+    target_sample_rate: Option<u32>,
+    /// Converts from the microphone's rate to the target rate, when they differ.
+    resampler: Option<Resampler>,
+    /// The samples from the latest recording, kept to save allocating them each time.
+    samples: Vec<f32>,
+    /// The same, resampled.
+    resampled: Vec<f32>,
+    chunker: Chunker,
 }
 
 impl State {
+    /// Start a fresh buffer, and resampler if needed, for a microphone recording at `sample_rate`.
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.buffer = Audio::with_silence(sample_rate, 0);
+        self.resampler = self
+            .target_sample_rate
+            .filter(|&target| target != sample_rate.round() as u32)
+            .map(|target| Resampler::new(sample_rate.round() as u32, target));
+        // the rest of the chunk would be at a different rate
+        self.chunker.clear();
+    }
+
     /// Some microphone event loop.
     fn event(&mut self, event: Event<'_>) {
         match event {
             // if we got an event of new audio recorded by the microphone,
-            // convert the audio to i16 pcm and send it along via a channel
+            // convert the audio to i16 pcm and send it along via the queue
             Event::Record(microphone_stream) => {
-                // TODO: hopefully there is another way, outside of a loop, to
-                // determine the sample rate. Empirically, I found it was 44100 Hz,
-                // but this may vary by system and browser.
-                //info!("Sample rate: {:?}.", microphone_stream.sample_rate());
+                let sample_rate = match usable_sample_rate(microphone_stream.sample_rate()) {
+                    Some(sample_rate) => sample_rate,
+                    None => {
+                        if !self.warned_about_sample_rate {
+                            warn!(
+                                "The microphone did not report a usable sample rate, so we are \
+                                guessing {} Hz. If this is wrong, Deepgram will hear garbled \
+                                audio and the puzzles may not recognize what you say.",
+                                FALLBACK_SAMPLE_RATE
+                            );
+                            self.warned_about_sample_rate = true;
+                        }
+                        FALLBACK_SAMPLE_RATE.into()
+                    }
+                };
+                // the device may have changed since the last recording
+                if sample_rate != self.buffer.sample_rate() {
+                    info!("Microphone sample rate is {} Hz.", sample_rate);
+                    self.set_sample_rate(sample_rate);
+                }
 
-                let mut audio_buffer = Vec::new();
+                // the stream is made of `Mono32` frames, so `fon` has already downmixed any
+                // other channels by the time we see them
                 self.buffer.extend(microphone_stream);
-
+                self.samples.clear();
                 for frame in self.buffer.drain() {
-                    let sample: f32 = frame.channels()[0].into();
-                    audio_buffer.push(f32_to_i16(sample));
+                    self.samples.push(frame.channels()[0].into());
                 }
 
-                let _ = self.tx.send(audio_buffer.to_owned());
+                match &mut self.resampler {
+                    Some(resampler) => {
+                        self.resampled.clear();
+                        resampler.process(&self.samples, &mut self.resampled);
+                        self.chunker.push(resampler.output_rate(), &self.resampled);
+                    }
+                    None => self.chunker.push(sample_rate.round() as u32, &self.samples),
+                }
             }
         }
     }
 }
 
+/// The sample rate the microphone reported, unless it's missing or one we can't record at, e.g.
+/// 0 Hz, which would leave the resampler with nothing to divide by.
+fn usable_sample_rate(reported: Option<f64>) -> Option<f64> {
+    reported.filter(|sample_rate| (1.0..=u32::MAX as f64).contains(&sample_rate.round()))
+}
+
 /// A helper function for converting f32 PCM samples to i16 (linear16) samples.
 /// Deepgram currently does not support f32 PCM.
-fn f32_to_i16(sample: f32) -> i16 {
+pub fn f32_to_i16(sample: f32) -> i16 {
     let sample = sample * 32768.0;
 
     // This is a saturating cast. For more details, see:
@@ -131,85 +288,31 @@ fn f32_to_i16(sample: f32) -> i16 {
     sample as i16
 }
 
-/// A helper function for converting a vector of i16 samples to Vec<u8>
-/// in order to pass on to our websocket client.
-pub fn to_vec_u8(input: Vec<i16>) -> Vec<u8> {
-    let mut vec_u8 = Vec::with_capacity(2 * input.len());
-
-    for value in input {
-        vec_u8.extend(&value.to_le_bytes());
-    }
-
-    vec_u8
-}
-
-fn setup_asr_handler(
-    mut commands: Commands,
-    mut deepgram_websocket: NonSendMut<DeepgramWebsocket>,
-) {
-    if let Some(client) = &mut deepgram_websocket.client {
-        // We're going to create a closure to receive websocket messages on. We can't just move an
-        // `EventWriter` into that closure to send messages from because the `EventWriter` is tied
-        // to the lifetime of the global `Events` queue and we can't easily communicate that this
-        // closure will outlive that. So instead we create a channel pair and push messages from
-        // the `tx` to the `rx` and then, in a separate system, we read from the `rx` and write to
-        // the `EventWriter`.
-        let (speech_events, rx) = crossbeam_channel::unbounded();
-        let closure = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
-            if let Ok(message) = e.data().dyn_into::<js_sys::JsString>() {
-                trace!("Received a message from Deepgram: {:?}", message);
-                if message.includes("sugar", 0) {
-                    info!("Sending sugar speech event.");
-                    speech_events.send(SpeechEvent::Sugar).unwrap();
-                }
-                if message.includes("mentos", 0) {
-                    info!("Sending mentos speech event.");
-                    speech_events.send(SpeechEvent::Mentos).unwrap();
-                }
-                if message.includes("mentors", 0) {
-                    info!("Sending mentos speech event.");
-                    speech_events.send(SpeechEvent::Mentos).unwrap();
-                }
-                if message.includes("mentor", 0) {
-                    info!("Sending mentos speech event.");
-                    speech_events.send(SpeechEvent::Mentos).unwrap();
-                }
-                if message.includes("bridge", 0) {
-                    info!("Sending bridge speech event.");
-                    speech_events.send(SpeechEvent::Bridge).unwrap();
-                }
-            }
-        });
-        client.set_onmessage(Some(closure.as_ref().unchecked_ref()));
-
-        // We need to forget this on the Rust side. If we didn't then, when this function finished,
-        // the `Closure` object (which is only passed _by reference_ to `set_onmessage`) would also
-        // be dropped. This leaks the closure so that it sticks around and is valid when we later
-        // receive messages on the websocket.
-        closure.forget();
+/// The microphone's event loop blocks the thread it runs on, so on the desktop it gets a thread of
+/// its own.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_in_background(task: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(task);
+}
 
-        commands.insert_resource(SpeechEventReceiver(rx));
-    }
+/// In the browser the microphone's event loop is driven by the page's own event loop, so it can
+/// simply be started here.
+#[cfg(target_arch = "wasm32")]
+fn run_in_background(task: impl FnOnce() + Send + 'static) {
+    task();
 }
 
-fn proxy_audio_to_deepgram(
-    microphone_receiver: Res<MicrophoneReceiver>,
-    mut deepgram_websocket: NonSendMut<DeepgramWebsocket>,
-) {
-    if let Some(client) = &mut deepgram_websocket.client {
-        if client.ready_state() != WebSocket::OPEN {
-            return;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        while let Ok(audio_buffer) = microphone_receiver.rx.try_recv() {
-            client.send_with_u8_array(&to_vec_u8(audio_buffer)).unwrap();
+    #[test]
+    fn only_usable_sample_rates_are_used() {
+        assert_eq!(usable_sample_rate(Some(48_000.0)), Some(48_000.0));
+        assert_eq!(usable_sample_rate(Some(44_099.6)), Some(44_099.6));
+        for unusable in [0.0, 0.4, -44_100.0, f64::NAN, f64::INFINITY, 1e12] {
+            assert_eq!(usable_sample_rate(Some(unusable)), None, "{}", unusable);
         }
+        assert_eq!(usable_sample_rate(None), None);
     }
 }
-
-fn proxy_speech_events(
-    speech_event_receiver: ResMut<SpeechEventReceiver>,
-    mut speech_events: EventWriter<SpeechEvent>,
-) {
-    speech_events.send_batch(speech_event_receiver.0.try_iter());
-}
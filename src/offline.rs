@@ -0,0 +1,537 @@
+//! Hearing the puzzle words without a network connection, as a `TranscriptionTransport`.
+//!
+//! Each player enrols by saying every puzzle word a few times. Later utterances are compared
+//! against those recordings with dynamic time warping over their MFCCs, and the closest keyword
+//! is transcribed if it is close enough. Everything runs on the CPU, in the game itself.
+use super::keywords::KeywordMatcher;
+use super::microphone::MicrophoneSource;
+use super::speech::{
+    AudioBuffer, KeywordMapper, SpeechBackend, SpeechConnectionStatus, Transcript, TranscriptWord,
+    TranscriptionTransport,
+};
+use super::Keyword;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+mod dtw;
+mod mfcc;
+mod segmenter;
+
+use mfcc::{Coefficients, Mfcc};
+use segmenter::{Segmenter, Utterance};
+
+/// Starts enrolment over, e.g. for the next player at a kiosk.
+const ENROL_KEY: KeyCode = KeyCode::F2;
+
+/// A word said at half or twice the speed of a recording still matches it, but not much beyond.
+const MAX_STRETCH: f32 = 2.0;
+
+/// Listens to the microphone with the offline keyword spotter.
+pub struct OfflineBackend;
+
+impl SpeechBackend for OfflineBackend {
+    type Source = MicrophoneSource;
+    type Transport = OfflineTransport;
+    type Mapper = KeywordMapper;
+}
+
+/// How the offline keyword spotter enrols and matches. Insert this resource before adding the
+/// `SpeechPlugin` to change it.
+#[derive(Clone, Debug)]
+pub struct OfflineSettings {
+    /// How many times the player says each keyword when enrolling. More takes cope better with
+    /// the player saying a word differently each time, but make enrolling take longer.
+    pub enrolment_takes: usize,
+    /// Utterances further than this from every recording aren't transcribed at all. Distances
+    /// are the average difference between aligned MFCC frames.
+    pub max_distance: f32,
+}
+
+impl Default for OfflineSettings {
+    fn default() -> Self {
+        OfflineSettings {
+            enrolment_takes: 3,
+            max_distance: 10.0,
+        }
+    }
+}
+
+/// A keyword as the player said it while enrolling.
+struct Template {
+    keyword: String,
+    features: Vec<Coefficients>,
+}
+
+enum Enrolment {
+    /// Waiting for the vocabulary to load.
+    NotStarted,
+    Recording {
+        keywords: Vec<String>,
+        /// Index into `keywords` of the keyword being recorded.
+        keyword: usize,
+        takes: usize,
+    },
+    Done,
+}
+
+pub struct OfflineTransport {
+    settings: OfflineSettings,
+    /// Built for the sample rate of the audio we're given, once we know it.
+    mfcc: Option<Mfcc>,
+    segmenter: Segmenter,
+    templates: Vec<Template>,
+    enrolment: Enrolment,
+    transcripts: VecDeque<Transcript>,
+}
+
+impl FromWorld for OfflineTransport {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world
+            .get_resource_or_insert_with(OfflineSettings::default)
+            .clone();
+        // there's nothing to connect to, so we can always hear the player
+        world.insert_resource(SpeechConnectionStatus::Open);
+
+        OfflineTransport {
+            settings,
+            mfcc: None,
+            segmenter: Segmenter::default(),
+            templates: Vec::new(),
+            enrolment: Enrolment::NotStarted,
+            transcripts: VecDeque::new(),
+        }
+    }
+}
+
+impl TranscriptionTransport for OfflineTransport {
+    fn add_systems(app: &mut App) {
+        app.add_startup_system(spawn_enrolment_prompt)
+            .add_system(start_enrolment)
+            .add_system(show_enrolment_prompt);
+    }
+
+    fn push_audio(&mut self, audio: AudioBuffer) {
+        if self.mfcc.as_ref().map(Mfcc::sample_rate) != Some(audio.sample_rate) {
+            self.mfcc = Some(Mfcc::new(audio.sample_rate));
+            self.segmenter.reset();
+        }
+
+        let mut frames = Vec::new();
+        if let Some(mfcc) = &mut self.mfcc {
+            mfcc.process(&audio.samples, &mut frames);
+        }
+        for frame in &frames {
+            if let Some(utterance) = self.segmenter.push(frame) {
+                self.hear(utterance);
+            }
+        }
+    }
+
+    fn try_transcript(&mut self) -> Option<Transcript> {
+        self.transcripts.pop_front()
+    }
+}
+
+impl OfflineTransport {
+    /// Forget the current recordings and have the player say each of `keywords` again.
+    fn start_enrolment(&mut self, keywords: Vec<String>) {
+        self.templates.clear();
+        self.enrolment = if keywords.is_empty() {
+            warn!("There are no puzzle keywords to enrol.");
+            Enrolment::Done
+        } else {
+            Enrolment::Recording {
+                keywords,
+                keyword: 0,
+                takes: 0,
+            }
+        };
+    }
+
+    fn hear(&mut self, mut utterance: Utterance) {
+        mfcc::normalize(&mut utterance.features);
+
+        if let Enrolment::Recording {
+            keywords,
+            keyword,
+            takes,
+        } = &mut self.enrolment
+        {
+            *takes += 1;
+            info!(
+                "Recorded {:?} ({} of {}).",
+                keywords[*keyword], takes, self.settings.enrolment_takes
+            );
+            self.templates.push(Template {
+                keyword: keywords[*keyword].clone(),
+                features: utterance.features,
+            });
+
+            if *takes >= self.settings.enrolment_takes {
+                *keyword += 1;
+                *takes = 0;
+            }
+            if *keyword == keywords.len() {
+                info!("Enrolment finished.");
+                self.enrolment = Enrolment::Done;
+            }
+            return;
+        }
+
+        // the closest recording of each keyword
+        let mut distances: Vec<(&String, f32)> = Vec::new();
+        for template in &self.templates {
+            let distance = dtw::distance(&template.features, &utterance.features, MAX_STRETCH);
+            match distances
+                .iter_mut()
+                .find(|(keyword, _)| **keyword == template.keyword)
+            {
+                Some((_, closest)) => *closest = closest.min(distance),
+                None => distances.push((&template.keyword, distance)),
+            }
+        }
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let (keyword, distance) = match distances.first() {
+            Some(&(keyword, distance)) if distance <= self.settings.max_distance => {
+                (keyword, distance)
+            }
+            _ => {
+                debug!("Heard something, but it wasn't close to any keyword.");
+                return;
+            }
+        };
+        // how much closer the keyword is than the next closest one, since something that sounds
+        // a bit like every keyword is probably none of them
+        let runner_up = distances
+            .get(1)
+            .map_or(self.settings.max_distance, |&(_, distance)| distance);
+        let confidence = (1.0 - distance / runner_up).clamp(0.0, 1.0);
+        debug!(
+            "Heard {:?} at distance {}, against {} for the next closest (confidence {}).",
+            keyword, distance, runner_up, confidence
+        );
+        self.transcripts.push_back(Transcript {
+            text: keyword.clone(),
+            words: vec![TranscriptWord {
+                word: keyword.clone(),
+                start: utterance.start,
+                end: utterance.end,
+                confidence,
+            }],
+            is_final: true,
+            stream: 0,
+        });
+    }
+
+    /// Write what to ask the player to say while they're enrolling into `prompt`, replacing what
+    /// was there, so that the same string can be reused every frame.
+    fn write_enrolment_prompt(&self, prompt: &mut String) {
+        prompt.clear();
+        if let Enrolment::Recording {
+            keywords,
+            keyword,
+            takes,
+        } = &self.enrolment
+        {
+            let _ = write!(
+                prompt,
+                "Say \"{}\" ({}/{})",
+                keywords[*keyword],
+                takes + 1,
+                self.settings.enrolment_takes
+            );
+        }
+    }
+}
+
+/// Enrol as soon as the vocabulary has loaded, and again whenever `ENROL_KEY` is pressed. Only
+/// the keywords some puzzle is listening for are enrolled.
+fn start_enrolment(
+    mut transport: NonSendMut<OfflineTransport>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    let keyword_matcher = match keyword_matcher {
+        Some(keyword_matcher) => keyword_matcher,
+        None => return,
+    };
+    if !matches!(transport.enrolment, Enrolment::NotStarted) && !keys.just_pressed(ENROL_KEY) {
+        return;
+    }
+
+    let keywords = keyword_matcher
+        .keywords()
+        .filter(|keyword| Keyword::from_name(keyword).is_some())
+        .map(str::to_string)
+        .collect();
+    transport.start_enrolment(keywords);
+}
+
+#[derive(Component)]
+struct EnrolmentPromptText;
+
+fn spawn_enrolment_prompt(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("kongtext.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(EnrolmentPromptText);
+}
+
+fn show_enrolment_prompt(
+    transport: NonSend<OfflineTransport>,
+    mut prompt: Local<String>,
+    mut text_query: Query<&mut Text, With<EnrolmentPromptText>>,
+) {
+    let mut text = text_query.single_mut();
+    transport.write_enrolment_prompt(&mut prompt);
+    if text.sections[0].value != *prompt {
+        text.sections[0].value.clone_from(&prompt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keywords::{KeywordEntry, MatchOptions, Vocabulary};
+    use mfcc::COEFFICIENTS;
+
+    fn transport(enrolment_takes: usize) -> OfflineTransport {
+        OfflineTransport {
+            settings: OfflineSettings {
+                enrolment_takes,
+                ..default()
+            },
+            mfcc: None,
+            segmenter: Segmenter::default(),
+            templates: Vec::new(),
+            enrolment: Enrolment::NotStarted,
+            transcripts: VecDeque::new(),
+        }
+    }
+
+    /// A made-up word: frames that move steadily through feature space, starting from `phase`.
+    /// Words with different phases sound different.
+    fn word(phase: f32) -> Utterance {
+        let features = (0..40)
+            .map(|i| {
+                let mut frame = [0.0; COEFFICIENTS];
+                for (k, coefficient) in frame.iter_mut().enumerate() {
+                    *coefficient = (i as f32 * 0.3 + k as f32 + phase).sin();
+                }
+                frame
+            })
+            .collect();
+        Utterance {
+            start: 1.0,
+            end: 1.5,
+            features,
+        }
+    }
+
+    fn keywords(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn prompt(transport: &OfflineTransport) -> String {
+        let mut prompt = "left over".to_string();
+        transport.write_enrolment_prompt(&mut prompt);
+        prompt
+    }
+
+    /// Enrol "bridge" and "sugar" as two quite different words, one take each.
+    fn enrolled() -> OfflineTransport {
+        let mut transport = transport(1);
+        transport.start_enrolment(keywords(&["bridge", "sugar"]));
+        transport.hear(word(0.0));
+        transport.hear(word(2.0));
+        transport
+    }
+
+    #[test]
+    fn enrolment_records_every_take_of_every_keyword() {
+        let mut transport = transport(2);
+        assert_eq!(prompt(&transport), "");
+        transport.start_enrolment(keywords(&["bridge", "sugar"]));
+
+        let mut prompts = Vec::new();
+        for phase in [0.0, 0.1, 2.0, 2.1] {
+            prompts.push(prompt(&transport));
+            transport.hear(word(phase));
+        }
+        assert_eq!(
+            prompts,
+            [
+                "Say \"bridge\" (1/2)",
+                "Say \"bridge\" (2/2)",
+                "Say \"sugar\" (1/2)",
+                "Say \"sugar\" (2/2)",
+            ]
+        );
+        let recorded: Vec<&str> = transport
+            .templates
+            .iter()
+            .map(|template| template.keyword.as_str())
+            .collect();
+        assert_eq!(recorded, ["bridge", "bridge", "sugar", "sugar"]);
+        assert!(matches!(transport.enrolment, Enrolment::Done));
+        assert_eq!(prompt(&transport), "");
+        // recordings aren't transcribed
+        assert!(transport.try_transcript().is_none());
+    }
+
+    #[test]
+    fn enrolling_nothing_is_done_straight_away() {
+        let mut transport = transport(3);
+        transport.start_enrolment(Vec::new());
+        assert!(matches!(transport.enrolment, Enrolment::Done));
+    }
+
+    #[test]
+    fn the_closest_keyword_is_heard_after_enrolling() {
+        let mut transport = enrolled();
+        transport.hear(word(2.0));
+
+        let transcript = transport.try_transcript().unwrap();
+        assert_eq!(transcript.text, "sugar");
+        assert!(transcript.is_final);
+        let heard = &transcript.words[0];
+        assert_eq!(heard.word, "sugar");
+        assert_eq!((heard.start, heard.end), (1.0, 1.5));
+        // it's exactly the recording, and nothing like the other keyword
+        assert_eq!(heard.confidence, 1.0);
+        assert!(transport.try_transcript().is_none());
+    }
+
+    #[test]
+    fn confidence_is_against_the_next_closest_keyword() {
+        let mut transport = enrolled();
+        transport.hear(word(0.3));
+
+        let mut features = word(0.3).features;
+        mfcc::normalize(&mut features);
+        let distance =
+            |template: &Template| dtw::distance(&template.features, &features, MAX_STRETCH);
+        let expected = 1.0 - distance(&transport.templates[0]) / distance(&transport.templates[1]);
+
+        let transcript = transport.try_transcript().unwrap();
+        assert_eq!(transcript.text, "bridge");
+        let confidence = transcript.words[0].confidence;
+        assert!(confidence > 0.0 && confidence < 1.0);
+        assert!((confidence - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sounding_like_every_keyword_is_no_confidence() {
+        let mut transport = transport(1);
+        transport.start_enrolment(keywords(&["bridge", "sugar"]));
+        // the player said the same thing for both
+        transport.hear(word(0.0));
+        transport.hear(word(0.0));
+
+        transport.hear(word(0.3));
+        assert_eq!(transport.try_transcript().unwrap().words[0].confidence, 0.0);
+    }
+
+    #[test]
+    fn one_keyword_is_compared_against_the_max_distance() {
+        let mut transport = transport(1);
+        transport.start_enrolment(keywords(&["bridge"]));
+        transport.hear(word(0.0));
+
+        transport.hear(word(0.3));
+        let mut features = word(0.3).features;
+        mfcc::normalize(&mut features);
+        let distance = dtw::distance(&transport.templates[0].features, &features, MAX_STRETCH);
+        let expected = 1.0 - distance / transport.settings.max_distance;
+        let confidence = transport.try_transcript().unwrap().words[0].confidence;
+        assert!((confidence - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn utterances_past_the_max_distance_are_not_heard() {
+        let mut transport = enrolled();
+        let mut loud = word(1.0);
+        for frame in &mut loud.features {
+            for coefficient in frame {
+                *coefficient *= 100.0;
+            }
+        }
+        transport.hear(loud);
+        assert!(transport.try_transcript().is_none());
+
+        transport.settings.max_distance = 0.0;
+        transport.hear(word(1.0));
+        assert!(transport.try_transcript().is_none());
+        // an exact match is still no distance at all
+        transport.hear(word(0.0));
+        assert_eq!(transport.try_transcript().unwrap().text, "bridge");
+    }
+
+    #[test]
+    fn f2_enrols_again() {
+        let mut app = App::new();
+        app.insert_non_send_resource(transport(1))
+            .insert_resource(Input::<KeyCode>::default())
+            .add_system(start_enrolment);
+        app.update();
+        // nothing to enrol until the vocabulary loads
+        assert!(matches!(
+            app.world.non_send_resource::<OfflineTransport>().enrolment,
+            Enrolment::NotStarted
+        ));
+
+        let entry = |keyword: &str| KeywordEntry {
+            keyword: keyword.to_string(),
+            aliases: Vec::new(),
+            boost: 2.0,
+        };
+        app.insert_resource(KeywordMatcher::new(&Vocabulary {
+            // no puzzle listens for "spam", so it isn't enrolled
+            keywords: vec![entry("bridge"), entry("spam"), entry("sugar")],
+            matching: MatchOptions::default(),
+        }));
+        app.update();
+        {
+            let mut transport = app.world.non_send_resource_mut::<OfflineTransport>();
+            assert_eq!(prompt(&transport), "Say \"bridge\" (1/1)");
+            transport.hear(word(0.0));
+            transport.hear(word(2.0));
+            assert!(matches!(transport.enrolment, Enrolment::Done));
+        }
+
+        // enrolment only starts over when asked
+        app.update();
+        assert_eq!(
+            app.world
+                .non_send_resource::<OfflineTransport>()
+                .templates
+                .len(),
+            2
+        );
+
+        app.world.resource_mut::<Input<KeyCode>>().press(ENROL_KEY);
+        app.update();
+        let transport = app.world.non_send_resource::<OfflineTransport>();
+        assert!(transport.templates.is_empty());
+        assert_eq!(prompt(transport), "Say \"bridge\" (1/1)");
+    }
+}
@@ -0,0 +1,122 @@
+//! Dynamic time warping: how different two sequences of feature frames are, allowing for one of
+//! them being said faster or slower than the other in places.
+use super::mfcc::Coefficients;
+
+/// The average distance between aligned frames along the cheapest alignment of `a` and `b`, or
+/// infinity if either is empty. Frames may be stretched to at most `max_stretch` times the
+/// length of the other sequence, which keeps a short word from matching part of a long one.
+pub fn distance(a: &[Coefficients], b: &[Coefficients], max_stretch: f32) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return f32::INFINITY;
+    }
+    let ratio = a.len().max(b.len()) as f32 / a.len().min(b.len()) as f32;
+    if ratio > max_stretch {
+        return f32::INFINITY;
+    }
+
+    // cost[j] holds the cheapest alignment of a[..=i] and b[..=j], along with its length, for the
+    // row `i` being filled in
+    let mut previous = vec![(f32::INFINITY, 0usize); b.len()];
+    let mut current = vec![(f32::INFINITY, 0usize); b.len()];
+    for (i, a_frame) in a.iter().enumerate() {
+        for (j, b_frame) in b.iter().enumerate() {
+            let cost = frame_distance(a_frame, b_frame);
+            let best = if i == 0 && j == 0 {
+                (0.0, 0)
+            } else {
+                let mut best = (f32::INFINITY, 0);
+                if i > 0 {
+                    best = cheaper(best, previous[j]);
+                }
+                if j > 0 {
+                    best = cheaper(best, current[j - 1]);
+                }
+                if i > 0 && j > 0 {
+                    best = cheaper(best, previous[j - 1]);
+                }
+                best
+            };
+            current[j] = (best.0 + cost, best.1 + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let (cost, length) = previous[b.len() - 1];
+    cost / length as f32
+}
+
+fn cheaper(a: (f32, usize), b: (f32, usize)) -> (f32, usize) {
+    if b.0 < a.0 {
+        b
+    } else {
+        a
+    }
+}
+
+fn frame_distance(a: &Coefficients, b: &Coefficients) -> f32 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::mfcc::COEFFICIENTS;
+    use crate::offline::MAX_STRETCH;
+
+    /// Frames that move steadily through feature space, so that no two are alike.
+    fn sweep(length: usize) -> Vec<Coefficients> {
+        (0..length)
+            .map(|i| {
+                let mut frame = [0.0; COEFFICIENTS];
+                for (k, coefficient) in frame.iter_mut().enumerate() {
+                    *coefficient = (i as f32 * 0.3 + k as f32).sin();
+                }
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_sequences_are_no_distance_apart() {
+        let a = sweep(40);
+        assert_eq!(distance(&a, &a, MAX_STRETCH), 0.0);
+    }
+
+    #[test]
+    fn slower_sequences_are_no_distance_apart() {
+        let a = sweep(40);
+        // every frame said for twice as long
+        let slower: Vec<Coefficients> = a.iter().flat_map(|&frame| [frame, frame]).collect();
+        assert_eq!(distance(&a, &slower, MAX_STRETCH), 0.0);
+        assert_eq!(distance(&slower, &a, MAX_STRETCH), 0.0);
+    }
+
+    #[test]
+    fn different_sequences_are_some_distance_apart() {
+        let a = sweep(40);
+        let b: Vec<Coefficients> = sweep(40).iter().map(|frame| frame.map(|c| -c)).collect();
+        let distance = distance(&a, &b, MAX_STRETCH);
+        assert!(distance > 1.0 && distance.is_finite(), "{}", distance);
+    }
+
+    #[test]
+    fn sequences_too_different_in_length_never_match() {
+        let a = sweep(20);
+        let b = sweep(20 * MAX_STRETCH as usize + 1);
+        assert_eq!(distance(&a, &b, MAX_STRETCH), f32::INFINITY);
+        assert_eq!(distance(&b, &a, MAX_STRETCH), f32::INFINITY);
+        // just within the limit
+        let c = sweep(20 * MAX_STRETCH as usize);
+        assert!(distance(&a, &c, MAX_STRETCH).is_finite());
+    }
+
+    #[test]
+    fn empty_sequences_never_match() {
+        assert_eq!(distance(&[], &sweep(10), MAX_STRETCH), f32::INFINITY);
+        assert_eq!(distance(&sweep(10), &[], MAX_STRETCH), f32::INFINITY);
+    }
+}
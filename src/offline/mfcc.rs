@@ -0,0 +1,189 @@
+//! Mel-frequency cepstral coefficients, the usual compact description of what a short slice of
+//! speech sounds like. Audio is fed in buffers of any size and comes out as one feature frame per
+//! `HOP_SECONDS`.
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// How much audio each frame describes. Speech is roughly stationary over this long.
+const WINDOW_SECONDS: f32 = 0.025;
+/// How far apart frames start.
+pub const HOP_SECONDS: f32 = 0.01;
+/// Boosts the high frequencies, which carry less energy but much of what tells consonants apart.
+const PRE_EMPHASIS: f32 = 0.97;
+const MEL_FILTERS: usize = 26;
+const MIN_FREQUENCY: f32 = 20.0;
+/// Telephone-quality speech is intelligible, so there's little to gain from going higher.
+const MAX_FREQUENCY: f32 = 8_000.0;
+/// How many coefficients each frame has. The zeroth coefficient is left out, since it mostly
+/// measures how loud the frame is.
+pub const COEFFICIENTS: usize = 12;
+
+pub type Coefficients = [f32; COEFFICIENTS];
+
+pub struct Frame {
+    /// Loudness in decibels relative to full scale.
+    pub energy_db: f32,
+    pub coefficients: Coefficients,
+}
+
+pub struct Mfcc {
+    sample_rate: u32,
+    window: Vec<f32>,
+    hop: usize,
+    fft: Arc<dyn Fft<f32>>,
+    /// For each mel filter, the first FFT bin it covers and its weight for each bin from there.
+    filters: Vec<(usize, Vec<f32>)>,
+    /// Samples that haven't made it into a whole frame yet, already pre-emphasized.
+    pending: Vec<f32>,
+    last_sample: f32,
+}
+
+impl Mfcc {
+    pub fn new(sample_rate: u32) -> Self {
+        let window_length = (sample_rate as f32 * WINDOW_SECONDS).round() as usize;
+        let hop = (sample_rate as f32 * HOP_SECONDS).round() as usize;
+        let fft_length = window_length.next_power_of_two();
+
+        // Hamming
+        let window = (0..window_length)
+            .map(|n| 0.54 - 0.46 * (2.0 * PI * n as f32 / (window_length - 1) as f32).cos())
+            .collect();
+
+        Mfcc {
+            sample_rate,
+            window,
+            hop: hop.max(1),
+            fft: FftPlanner::new().plan_fft_forward(fft_length),
+            filters: mel_filters(sample_rate, fft_length),
+            pending: Vec::new(),
+            last_sample: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Add some audio, appending a frame to `frames` for each one it completes.
+    pub fn process(&mut self, samples: &[i16], frames: &mut Vec<Frame>) {
+        for &sample in samples {
+            let sample = sample as f32 / 32768.0;
+            self.pending.push(sample - PRE_EMPHASIS * self.last_sample);
+            self.last_sample = sample;
+        }
+
+        let mut start = 0;
+        while start + self.window.len() <= self.pending.len() {
+            frames.push(self.frame(&self.pending[start..start + self.window.len()]));
+            start += self.hop;
+        }
+        self.pending.drain(..start);
+    }
+
+    fn frame(&self, samples: &[f32]) -> Frame {
+        let energy =
+            samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+
+        let mut spectrum = vec![Complex::new(0.0, 0.0); self.fft.len()];
+        for ((bin, sample), weight) in spectrum.iter_mut().zip(samples).zip(&self.window) {
+            bin.re = sample * weight;
+        }
+        self.fft.process(&mut spectrum);
+
+        let log_energies: Vec<f32> = self
+            .filters
+            .iter()
+            .map(|(first_bin, weights)| {
+                let energy: f32 = spectrum[*first_bin..]
+                    .iter()
+                    .zip(weights)
+                    .map(|(bin, weight)| bin.norm_sqr() * weight)
+                    .sum();
+                energy.max(1e-10).ln()
+            })
+            .collect();
+
+        // an orthonormal DCT-II, skipping the zeroth coefficient
+        let mut coefficients = [0.0; COEFFICIENTS];
+        let scale = (2.0 / MEL_FILTERS as f32).sqrt();
+        for (k, coefficient) in coefficients.iter_mut().enumerate() {
+            let k = k + 1;
+            *coefficient = scale
+                * log_energies
+                    .iter()
+                    .enumerate()
+                    .map(|(n, energy)| {
+                        energy * (PI * k as f32 * (n as f32 + 0.5) / MEL_FILTERS as f32).cos()
+                    })
+                    .sum::<f32>();
+        }
+
+        Frame {
+            energy_db: 10.0 * energy.max(1e-10).log10(),
+            coefficients,
+        }
+    }
+}
+
+/// Subtract the average of each coefficient, which cancels out most of the differences between
+/// microphones and rooms.
+pub fn normalize(features: &mut [Coefficients]) {
+    if features.is_empty() {
+        return;
+    }
+
+    let mut mean = [0.0; COEFFICIENTS];
+    for frame in features.iter() {
+        for (mean, coefficient) in mean.iter_mut().zip(frame) {
+            *mean += coefficient / features.len() as f32;
+        }
+    }
+    for frame in features.iter_mut() {
+        for (coefficient, mean) in frame.iter_mut().zip(&mean) {
+            *coefficient -= mean;
+        }
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Triangular filters spaced evenly on the mel scale, each peaking where its neighbours end.
+fn mel_filters(sample_rate: u32, fft_length: usize) -> Vec<(usize, Vec<f32>)> {
+    let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+    let min_mel = hz_to_mel(MIN_FREQUENCY);
+    let max_mel = hz_to_mel(max_frequency);
+    let bin_width = sample_rate as f32 / fft_length as f32;
+
+    let edges: Vec<f32> = (0..MEL_FILTERS + 2)
+        .map(|i| mel_to_hz(min_mel + (max_mel - min_mel) * i as f32 / (MEL_FILTERS + 1) as f32))
+        .collect();
+
+    edges
+        .windows(3)
+        .map(|edges| {
+            let (low, center, high) = (edges[0], edges[1], edges[2]);
+            let first_bin = (low / bin_width).ceil() as usize;
+            let last_bin = ((high / bin_width).floor() as usize).min(fft_length / 2);
+            let weights = (first_bin..=last_bin)
+                .map(|bin| {
+                    let frequency = bin as f32 * bin_width;
+                    if frequency <= center {
+                        (frequency - low) / (center - low)
+                    } else {
+                        (high - frequency) / (high - center)
+                    }
+                    .max(0.0)
+                })
+                .collect();
+            (first_bin, weights)
+        })
+        .collect()
+}
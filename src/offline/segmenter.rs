@@ -0,0 +1,192 @@
+//! Splitting a stream of feature frames into utterances, by watching for frames that are clearly
+//! louder than the background noise.
+use super::mfcc::{Coefficients, Frame, HOP_SECONDS};
+use std::collections::VecDeque;
+
+/// How far above the background noise a frame has to be to count as speech.
+const SPEECH_ABOVE_NOISE_DB: f32 = 12.0;
+/// Frames quieter than this are never speech, however quiet the room.
+const MIN_SPEECH_DB: f32 = -55.0;
+/// How quickly the noise estimate follows quieter and louder frames. It falls quickly, but rises
+/// slowly enough that a word doesn't get mistaken for noise before it's over. It still rises
+/// during speech, so that a noisier room can't leave us hearing one endless utterance.
+const NOISE_FALL_RATE: f32 = 0.2;
+const NOISE_RISE_RATE: f32 = 0.005;
+/// Frames kept from before speech starts, since words tend to start softly.
+const PRE_ROLL_FRAMES: usize = 10;
+/// Frames kept from after speech stops, for the same reason.
+const POST_ROLL_FRAMES: usize = 5;
+/// This much quiet ends an utterance.
+const END_SILENCE_FRAMES: usize = 30;
+/// Shorter utterances are clicks and bumps rather than words.
+const MIN_SPEECH_FRAMES: usize = 15;
+/// Puzzle words are short, so anything longer is cut off here rather than left to grow forever.
+const MAX_UTTERANCE_FRAMES: usize = 200;
+
+/// A stretch of speech, with its start and end in seconds since the start of the stream.
+pub struct Utterance {
+    pub start: f32,
+    pub end: f32,
+    pub features: Vec<Coefficients>,
+}
+
+#[derive(Default)]
+pub struct Segmenter {
+    noise_db: Option<f32>,
+    /// How many frames we've seen, which tells us where we are in the stream.
+    frames_seen: usize,
+    pre_roll: VecDeque<Coefficients>,
+    utterance: Option<InProgress>,
+}
+
+struct InProgress {
+    start_frame: usize,
+    features: Vec<Coefficients>,
+    speech_frames: usize,
+    trailing_silence: usize,
+}
+
+impl Segmenter {
+    /// Forget any utterance in progress, e.g. when the audio stream restarts.
+    pub fn reset(&mut self) {
+        *self = Segmenter::default();
+    }
+
+    /// Add a frame, returning the utterance it ends, if any.
+    pub fn push(&mut self, frame: &Frame) -> Option<Utterance> {
+        let noise_db = *self.noise_db.get_or_insert(frame.energy_db);
+        let is_speech =
+            frame.energy_db > noise_db + SPEECH_ABOVE_NOISE_DB && frame.energy_db > MIN_SPEECH_DB;
+        let rate = if frame.energy_db < noise_db {
+            NOISE_FALL_RATE
+        } else {
+            NOISE_RISE_RATE
+        };
+        self.noise_db = Some(noise_db + (frame.energy_db - noise_db) * rate);
+        self.frames_seen += 1;
+
+        let utterance = match &mut self.utterance {
+            Some(utterance) => utterance,
+            None if is_speech => {
+                let features: Vec<Coefficients> = self.pre_roll.drain(..).collect();
+                self.utterance.insert(InProgress {
+                    start_frame: self.frames_seen - 1 - features.len(),
+                    features,
+                    speech_frames: 0,
+                    trailing_silence: 0,
+                })
+            }
+            None => {
+                if self.pre_roll.len() == PRE_ROLL_FRAMES {
+                    self.pre_roll.pop_front();
+                }
+                self.pre_roll.push_back(frame.coefficients);
+                return None;
+            }
+        };
+
+        utterance.features.push(frame.coefficients);
+        if is_speech {
+            utterance.speech_frames += 1;
+            utterance.trailing_silence = 0;
+        } else {
+            utterance.trailing_silence += 1;
+        }
+
+        if utterance.trailing_silence < END_SILENCE_FRAMES
+            && utterance.features.len() < MAX_UTTERANCE_FRAMES
+        {
+            return None;
+        }
+
+        let mut utterance = self.utterance.take()?;
+        if utterance.speech_frames < MIN_SPEECH_FRAMES {
+            return None;
+        }
+        let trimmed = utterance.trailing_silence.saturating_sub(POST_ROLL_FRAMES);
+        utterance
+            .features
+            .truncate(utterance.features.len() - trimmed);
+
+        Some(Utterance {
+            start: utterance.start_frame as f32 * HOP_SECONDS,
+            end: (utterance.start_frame + utterance.features.len()) as f32 * HOP_SECONDS,
+            features: utterance.features,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::mfcc::COEFFICIENTS;
+
+    const QUIET: Frame = Frame {
+        energy_db: -70.0,
+        coefficients: [0.0; COEFFICIENTS],
+    };
+    const LOUD: Frame = Frame {
+        energy_db: -20.0,
+        coefficients: [1.0; COEFFICIENTS],
+    };
+
+    /// Feed `silence` quiet frames, then `speech` loud ones, then a second of quiet, returning the
+    /// utterances that come out.
+    fn segment(silence: usize, speech: usize) -> Vec<Utterance> {
+        let mut segmenter = Segmenter::default();
+        let frames = std::iter::repeat_n(&QUIET, silence)
+            .chain(std::iter::repeat_n(&LOUD, speech))
+            .chain(std::iter::repeat_n(&QUIET, 100));
+        frames.filter_map(|frame| segmenter.push(frame)).collect()
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn a_burst_is_one_utterance() {
+        let utterances = segment(50, 30);
+        assert_eq!(utterances.len(), 1);
+
+        // it starts with the pre-roll and ends with the post-roll
+        let utterance = &utterances[0];
+        assert_close(utterance.start, (50 - PRE_ROLL_FRAMES) as f32 * HOP_SECONDS);
+        assert_close(
+            utterance.end,
+            (50 + 30 + POST_ROLL_FRAMES) as f32 * HOP_SECONDS,
+        );
+        assert_eq!(
+            utterance.features.len(),
+            PRE_ROLL_FRAMES + 30 + POST_ROLL_FRAMES
+        );
+        assert_eq!(utterance.features[PRE_ROLL_FRAMES - 1], QUIET.coefficients);
+        assert_eq!(utterance.features[PRE_ROLL_FRAMES], LOUD.coefficients);
+        assert_eq!(utterance.features[PRE_ROLL_FRAMES + 29], LOUD.coefficients);
+    }
+
+    #[test]
+    fn short_bursts_are_not_utterances() {
+        assert!(segment(50, MIN_SPEECH_FRAMES - 1).is_empty());
+        assert_eq!(segment(50, MIN_SPEECH_FRAMES).len(), 1);
+    }
+
+    #[test]
+    fn long_bursts_are_cut_off() {
+        let utterances = segment(50, MAX_UTTERANCE_FRAMES * 2);
+        assert!(!utterances.is_empty());
+        assert!(utterances
+            .iter()
+            .all(|utterance| utterance.features.len() <= MAX_UTTERANCE_FRAMES));
+    }
+
+    #[test]
+    fn silence_is_not_an_utterance() {
+        assert!(segment(500, 0).is_empty());
+    }
+}
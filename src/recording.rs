@@ -0,0 +1,267 @@
+//! Recording the audio the game hears to a WAV file, alongside a log of the speech events that
+//! fired, so that "it didn't hear me" can be looked into afterwards.
+//!
+//! On the desktop, set `JAMFEST_RECORD_SESSION` to the directory to save recordings in. In the
+//! browser, add a `record_session` query parameter to the page's URL and the recording is
+//! offered as a download. Either way, press `SAVE_KEY` to save what has been recorded so far.
+use super::speech::AudioBuffer;
+use super::{SpeechEvent, TentativeSpeechEvent};
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use std::fmt::Write;
+
+const SAVE_KEY: KeyCode = KeyCode::F3;
+
+/// Records the session if asked to, by inserting a `SessionRecording`.
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        let destination = match destination() {
+            Some(destination) => destination,
+            None => return,
+        };
+        info!("Recording the session.");
+
+        app.insert_resource(SessionRecording::new(destination))
+            .add_system(log_speech_events)
+            .add_system(save_recording);
+    }
+}
+
+/// The audio heard so far this session, and the speech events it led to. While this resource
+/// exists, every buffer of audio passed to the speech transport is added to it.
+pub struct SessionRecording {
+    destination: String,
+    /// When the session started, which names the files.
+    started: u64,
+    sample_rate: Option<u32>,
+    samples: Vec<i16>,
+    log: String,
+}
+
+impl SessionRecording {
+    fn new(destination: String) -> Self {
+        SessionRecording {
+            destination,
+            started: seconds_since_epoch(),
+            sample_rate: None,
+            samples: Vec::new(),
+            log: String::new(),
+        }
+    }
+
+    pub fn push_audio(&mut self, audio: &AudioBuffer) {
+        if self.sample_rate != Some(audio.sample_rate) {
+            // a WAV file only has the one rate, so a new rate means a new file
+            if !self.samples.is_empty() {
+                self.save();
+                self.samples.clear();
+                self.log.clear();
+                self.started = seconds_since_epoch();
+            }
+            self.sample_rate = Some(audio.sample_rate);
+            let _ = writeln!(
+                self.log,
+                "# seconds into the recording, event, keyword, confidence, where in the audio \
+                stream it was said, transcript"
+            );
+        }
+        self.samples.extend_from_slice(&audio.samples);
+    }
+
+    /// How far into the recording we are.
+    fn seconds(&self) -> f32 {
+        self.sample_rate.map_or(0.0, |sample_rate| {
+            self.samples.len() as f32 / sample_rate as f32
+        })
+    }
+
+    fn log_event(&mut self, kind: &str, event: &SpeechEvent) {
+        let _ = writeln!(
+            self.log,
+            "{:.2}\t{}\t{:?}\t{:.2}\t{:.2}-{:.2}\t{:?}",
+            self.seconds(),
+            kind,
+            event.keyword,
+            event.confidence,
+            event.start,
+            event.end,
+            event.transcript
+        );
+    }
+
+    fn save(&self) {
+        let sample_rate = match self.sample_rate {
+            Some(sample_rate) => sample_rate,
+            None => return,
+        };
+        let name = format!("jamfest-session-{}", self.started);
+        platform_save(
+            &self.destination,
+            &name,
+            &wav(sample_rate, &self.samples),
+            &self.log,
+        );
+    }
+}
+
+/// Keyboard shortcuts don't come from the audio, so they're logged with their source.
+fn log_speech_events(
+    mut recording: ResMut<SessionRecording>,
+    mut tentative_speech_events: EventReader<TentativeSpeechEvent>,
+    mut speech_events: EventReader<SpeechEvent>,
+) {
+    for TentativeSpeechEvent(event) in tentative_speech_events.iter() {
+        recording.log_event("tentative", event);
+    }
+    for event in speech_events.iter() {
+        let kind = format!("{:?}", event.source).to_lowercase();
+        recording.log_event(&kind, event);
+    }
+}
+
+/// Save when asked to, and when the game exits.
+fn save_recording(
+    recording: Res<SessionRecording>,
+    keys: Res<Input<KeyCode>>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if keys.just_pressed(SAVE_KEY) || exit_events.iter().next().is_some() {
+        recording.save();
+    }
+}
+
+/// A mono 16-bit PCM WAV file.
+fn wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+    let data_length = (samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + samples.len() * 2);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes()); // bytes per frame
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_length.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    bytes
+}
+
+/// The directory to save recordings in, if we should record at all.
+#[cfg(not(target_arch = "wasm32"))]
+fn destination() -> Option<String> {
+    std::env::var("JAMFEST_RECORD_SESSION").ok()
+}
+
+/// Recordings are downloaded rather than saved anywhere in particular.
+#[cfg(target_arch = "wasm32")]
+fn destination() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let parameters = web_sys::UrlSearchParams::new_with_str(&search).ok()?;
+    parameters.has("record_session").then(String::new)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn seconds_since_epoch() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn seconds_since_epoch() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn platform_save(directory: &str, name: &str, wav: &[u8], log: &str) {
+    let directory = std::path::Path::new(directory);
+    let wav_path = directory.join(format!("{}.wav", name));
+    let log_path = directory.join(format!("{}.txt", name));
+    let saved = std::fs::create_dir_all(directory)
+        .and_then(|()| std::fs::write(&wav_path, wav))
+        .and_then(|()| std::fs::write(&log_path, log));
+    match saved {
+        Ok(()) => info!("Saved the session recording to {}.", wav_path.display()),
+        Err(error) => error!("Could not save the session recording: {}", error),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn platform_save(_directory: &str, name: &str, wav: &[u8], log: &str) {
+    let downloaded = download(&format!("{}.wav", name), "audio/wav", wav)
+        .and_then(|()| download(&format!("{}.txt", name), "text/plain", log.as_bytes()));
+    match downloaded {
+        Ok(()) => info!("Downloading the session recording as {}.wav.", name),
+        Err(error) => error!("Could not download the session recording: {:?}", error),
+    }
+}
+
+/// Offer `bytes` as a download, by clicking a link to them.
+#[cfg(target_arch = "wasm32")]
+fn download(name: &str, mime_type: &str, bytes: &[u8]) -> Result<(), wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+    use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = Blob::new_with_u8_array_sequence_and_options(
+        &parts,
+        BlobPropertyBag::new().type_(mime_type),
+    )?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("no document")?;
+    let link: HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    // the download may not have started yet, so the URL is left for the page to clean up
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_file::read_wav;
+
+    #[test]
+    fn wav_files_read_back_as_written() {
+        let samples = [0, 1, -1, 16384, -16384, i16::MAX, i16::MIN];
+        let bytes = wav(44_100, &samples);
+
+        let (sample_rate, read) = read_wav(&bytes).expect("a valid WAV file");
+        assert_eq!(sample_rate, 44_100);
+        let expected: Vec<f32> = samples
+            .iter()
+            .map(|&sample| sample as f32 / 32768.0)
+            .collect();
+        assert_eq!(read, expected);
+
+        // the RIFF size covers everything after itself, and the data size just the samples
+        let riff_size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        assert_eq!(riff_size as usize, bytes.len() - 8);
+        assert_eq!(&bytes[36..40], b"data");
+        let data_size = u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]);
+        assert_eq!(data_size as usize, samples.len() * 2);
+        assert_eq!(bytes.len(), 44 + samples.len() * 2);
+    }
+
+    #[test]
+    fn empty_recordings_are_valid_wav_files() {
+        let bytes = wav(16_000, &[]);
+        assert_eq!(read_wav(&bytes), Ok((16_000, Vec::new())));
+        assert_eq!(bytes.len(), 44);
+    }
+}
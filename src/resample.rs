@@ -0,0 +1,255 @@
+//! A streaming polyphase resampler using a windowed-sinc low-pass filter. Audio can be fed in
+//! buffers of any size and comes out as one continuous stream at the new rate.
+
+/// How many zero crossings of the sinc to keep on either side of its peak, measured at the lower
+/// of the two rates. More is sharper but slower.
+const ZERO_CROSSINGS: usize = 32;
+/// Where the filter cuts off, as a fraction of the lower rate's Nyquist frequency. Leaving a little
+/// room below Nyquist keeps the transition band from aliasing.
+const CUTOFF: f64 = 0.9;
+
+pub struct Resampler {
+    output_rate: u32,
+    /// Output samples are produced `up` times per input sample, then all but every `down`th are
+    /// thrown away. Of course we never actually compute the ones we throw away.
+    up: usize,
+    down: usize,
+    /// The filter split into `up` phases, each reversed so it can be applied to a run of input
+    /// samples with a plain dot product.
+    phases: Vec<Vec<f32>>,
+    /// Input samples that the next outputs still depend on.
+    history: Vec<f32>,
+    /// Index into `history` of the newest input sample the next output depends on.
+    index: usize,
+    /// Which phase of the filter the next output uses.
+    phase: usize,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        assert!(
+            input_rate > 0 && output_rate > 0,
+            "sample rates must be positive"
+        );
+
+        let divisor = gcd(input_rate, output_rate);
+        let up = (output_rate / divisor) as usize;
+        let down = (input_rate / divisor) as usize;
+
+        let phases = if up == down {
+            vec![vec![1.0]]
+        } else {
+            polyphase_filter(up, down)
+        };
+        let taps = phases[0].len();
+
+        Resampler {
+            output_rate,
+            up,
+            down,
+            phases,
+            history: vec![0.0; taps - 1],
+            index: taps - 1,
+            phase: 0,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    /// Resample `input`, appending to `output` every sample that can be computed so far. Samples
+    /// near the end of `input` are held on to until the next call needs them.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let taps = self.phases[0].len();
+        self.history.extend_from_slice(input);
+
+        while self.index < self.history.len() {
+            let window = &self.history[self.index + 1 - taps..=self.index];
+            let sample = window
+                .iter()
+                .zip(&self.phases[self.phase])
+                .map(|(x, h)| x * h)
+                .sum();
+            output.push(sample);
+
+            self.phase += self.down;
+            self.index += self.phase / self.up;
+            self.phase %= self.up;
+        }
+
+        let consumed = self.index + 1 - taps;
+        self.history.drain(..consumed);
+        self.index -= consumed;
+    }
+}
+
+/// A low-pass filter at `up` times the input rate, cut off below the Nyquist frequency of the
+/// lower of the two rates, and split into `up` phases.
+fn polyphase_filter(up: usize, down: usize) -> Vec<Vec<f32>> {
+    let ratio = up.max(down);
+    let taps = (2 * ZERO_CROSSINGS * ratio).div_ceil(up);
+    let length = taps * up;
+    let center = (length - 1) as f64 / 2.0;
+    // in cycles per sample at the upsampled rate
+    let cutoff = CUTOFF / (2.0 * ratio as f64);
+
+    let prototype: Vec<f64> = (0..length)
+        .map(|i| {
+            let t = i as f64 - center;
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                let x = std::f64::consts::PI * 2.0 * cutoff * t;
+                x.sin() / x
+            };
+            // zero stuffing divides the signal's level by `up`, so the filter makes it back up
+            2.0 * cutoff * sinc * blackman(i, length) * up as f64
+        })
+        .collect();
+
+    (0..up)
+        .map(|phase| {
+            let mut coefficients: Vec<f32> = (0..taps)
+                .map(|tap| prototype[phase + tap * up] as f32)
+                .collect();
+            coefficients.reverse();
+            coefficients
+        })
+        .collect()
+}
+
+fn blackman(i: usize, length: usize) -> f64 {
+    let x = 2.0 * std::f64::consts::PI * i as f64 / (length - 1) as f64;
+    0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos()
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::microphone::f32_to_i16;
+    use std::f32::consts::PI;
+
+    const OUTPUT_RATE: u32 = 16_000;
+
+    fn sine(frequency: f32, sample_rate: u32, seconds: f32) -> Vec<f32> {
+        (0..(sample_rate as f32 * seconds) as usize)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    /// Feed the input in uneven buffers, as the microphone does.
+    fn resample(input: &[f32], input_rate: u32) -> Vec<f32> {
+        let mut resampler = Resampler::new(input_rate, OUTPUT_RATE);
+        let mut output = Vec::new();
+        for buffer in input.chunks(441) {
+            resampler.process(buffer, &mut output);
+        }
+        output
+    }
+
+    /// The output after the filter has filled up with input, and before the input runs out.
+    fn settled(output: &[f32]) -> &[f32] {
+        &output[output.len() / 4..output.len() * 3 / 4]
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// Estimated from how often the signal goes from negative to positive.
+    fn frequency(samples: &[f32], sample_rate: u32) -> f32 {
+        let rising = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        rising as f32 * sample_rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn tones_keep_their_frequency_and_level() {
+        for input_rate in [44_100, 48_000] {
+            let output = resample(&sine(1000.0, input_rate, 1.0), input_rate);
+
+            assert!(
+                output.len().abs_diff(OUTPUT_RATE as usize) <= 1,
+                "{} samples from {} Hz",
+                output.len(),
+                input_rate
+            );
+            let output = settled(&output);
+            let frequency = frequency(output, OUTPUT_RATE);
+            assert!(
+                (frequency - 1000.0).abs() < 5.0,
+                "{} Hz from {} Hz",
+                frequency,
+                input_rate
+            );
+            // a sine of amplitude 0.5 has an RMS of 0.5 / √2
+            assert!(
+                (rms(output) - 0.5 / 2f32.sqrt()).abs() < 0.01,
+                "RMS {} from {} Hz",
+                rms(output),
+                input_rate
+            );
+        }
+    }
+
+    #[test]
+    fn buffer_sizes_do_not_matter() {
+        let input = sine(1000.0, 44_100, 0.5);
+        let mut resampler = Resampler::new(44_100, OUTPUT_RATE);
+        let mut whole = Vec::new();
+        resampler.process(&input, &mut whole);
+
+        assert_eq!(resample(&input, 44_100), whole);
+    }
+
+    #[test]
+    fn tones_above_the_output_nyquist_frequency_are_removed() {
+        for (input_rate, tone) in [(44_100, 10_000.0), (48_000, 12_000.0), (48_000, 20_000.0)] {
+            let output = resample(&sine(tone, input_rate, 1.0), input_rate);
+            // anything left over would alias to somewhere below 8 kHz, so it should be at least
+            // 40 dB down
+            let level = rms(settled(&output)) / (0.5 / 2f32.sqrt());
+            assert!(
+                level < 0.01,
+                "{} Hz at {} Hz came through at {}",
+                tone,
+                input_rate,
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn matching_rates_pass_audio_through() {
+        let input = sine(1000.0, OUTPUT_RATE, 0.1);
+        let mut resampler = Resampler::new(OUTPUT_RATE, OUTPUT_RATE);
+        let mut output = Vec::new();
+        resampler.process(&input, &mut output);
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn conversion_to_i16_clamps() {
+        assert_eq!(f32_to_i16(0.0), 0);
+        assert_eq!(f32_to_i16(0.5), 16384);
+        assert_eq!(f32_to_i16(-0.5), -16384);
+        assert_eq!(f32_to_i16(1.0), i16::MAX);
+        assert_eq!(f32_to_i16(-1.0), i16::MIN);
+        assert_eq!(f32_to_i16(1.5), i16::MAX);
+        assert_eq!(f32_to_i16(-1.5), i16::MIN);
+        assert_eq!(f32_to_i16(f32::INFINITY), i16::MAX);
+    }
+}
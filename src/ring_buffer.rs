@@ -0,0 +1,94 @@
+//! A queue shared between threads that only holds so much, throwing away the oldest items to make
+//! room for new ones. Audio and transcripts are only worth having while they're fresh, so when the
+//! game falls behind it's better to lose the old ones than to catch up on them minutes late.
+use super::speech::QueueCounts;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Each clone is a handle to the same queue.
+pub struct RingBuffer<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+struct Shared<T> {
+    items: VecDeque<T>,
+    /// The total size of `items`.
+    size: f32,
+    capacity: f32,
+    size_of: fn(&T) -> f32,
+    counts: QueueCounts,
+}
+
+impl<T> Clone for RingBuffer<T> {
+    fn clone(&self) -> Self {
+        RingBuffer {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> RingBuffer<T> {
+    /// A queue holding items up to a total size of `capacity`, where `size_of` gives each item's
+    /// size, e.g. the length of a buffer of audio in seconds.
+    pub fn new(capacity: f32, size_of: fn(&T) -> f32) -> Self {
+        RingBuffer {
+            shared: Arc::new(Mutex::new(Shared {
+                items: VecDeque::new(),
+                size: 0.0,
+                capacity,
+                size_of,
+                counts: QueueCounts::default(),
+            })),
+        }
+    }
+
+    /// A queue holding up to `capacity` items.
+    pub fn with_length(capacity: usize) -> Self {
+        RingBuffer::new(capacity as f32, |_| 1.0)
+    }
+
+    /// Add an item, dropping the oldest ones if there isn't room for it. The newest item is always
+    /// kept, even if it's too big on its own. Returns how many items were dropped.
+    pub fn push(&self, item: T) -> usize {
+        let mut shared = self.lock();
+        shared.size += (shared.size_of)(&item);
+        shared.items.push_back(item);
+
+        let mut dropped = 0;
+        while shared.size > shared.capacity && shared.items.len() > 1 {
+            if let Some(oldest) = shared.items.pop_front() {
+                shared.size -= (shared.size_of)(&oldest);
+                dropped += 1;
+            }
+        }
+        shared.counts.dropped += dropped as u64;
+        dropped
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut shared = self.lock();
+        let item = shared.items.pop_front()?;
+        shared.size -= (shared.size_of)(&item);
+        shared.counts.delivered += 1;
+        Some(item)
+    }
+
+    /// How many items have been taken out of the queue, and how many were dropped.
+    pub fn counts(&self) -> QueueCounts {
+        self.lock().counts
+    }
+
+    /// Whether every other handle to the queue has been dropped, so that nothing will ever take
+    /// out anything we add.
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+
+    /// A thread that panicked while holding the lock can't have left the queue in a state we
+    /// can't use, so we carry on regardless.
+    fn lock(&self) -> MutexGuard<'_, Shared<T>> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,596 @@
+//! The pieces a speech backend is made of, and the plugin that wires them into the game.
+//!
+//! A backend is an `AudioSource` that records audio, a `TranscriptionTransport` that turns that
+//! audio into transcripts, and a `TranscriptMapper` that turns transcripts into the speech events
+//! the puzzles listen for. Swapping any of them doesn't affect the rest of the game.
+use super::keywords::KeywordMatcher;
+use super::recording::SessionRecording;
+use super::speech_input::SpeechInput;
+use super::vad::VoiceActivityDetector;
+use super::{Keyword, SpeechEvent, SpeechSource, TentativeSpeechEvent};
+use bevy::prelude::*;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A buffer of mono linear16 samples, along with their sample rate.
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl AudioBuffer {
+    /// Length of the buffer in seconds.
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate as f32
+    }
+}
+
+/// What a transport heard in some span of the audio.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub words: Vec<TranscriptWord>,
+    /// Whether the transport will send any more revisions of this span.
+    pub is_final: bool,
+    /// Which stream of audio the word timings are relative to. Transports that restart their
+    /// stream, e.g. by reconnecting, give each one a new number.
+    pub stream: u32,
+}
+
+impl Transcript {
+    /// A transcript of typed words. They're as certain as words can be, and don't come from
+    /// anywhere in the audio.
+    pub fn typed(text: &str) -> Self {
+        Transcript {
+            text: text.to_string(),
+            words: text
+                .split_whitespace()
+                .map(|word| TranscriptWord {
+                    word: word.to_lowercase(),
+                    start: 0.0,
+                    end: 0.0,
+                    confidence: 1.0,
+                })
+                .collect(),
+            is_final: true,
+            stream: 0,
+        }
+    }
+}
+
+/// Sent for every transcript the transport produces, interim or final, for anything that wants to
+/// show or log what the recognizer heard rather than just the keywords.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TranscriptEvent(pub Transcript);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TranscriptWord {
+    pub word: String,
+    /// Offset from the start of the audio stream, in seconds.
+    pub start: f32,
+    pub end: f32,
+    pub confidence: f32,
+}
+
+/// Where audio comes from, such as the microphone. Created when the `SpeechPlugin` is added.
+pub trait AudioSource: FromWorld + Send + Sync + 'static {
+    /// The next buffer of audio, if any has been recorded since the last call.
+    fn try_read(&mut self) -> Option<AudioBuffer>;
+
+    /// How many buffers of audio have been read, and how many were dropped because they weren't
+    /// read in time.
+    fn counts(&self) -> QueueCounts {
+        QueueCounts::default()
+    }
+
+    /// Whether the source has stopped for good, e.g. because the thread recording the audio died.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Hand back a buffer that's been read and is no longer needed, so that the source can fill
+    /// it again rather than allocate another.
+    fn recycle(&mut self, _audio: AudioBuffer) {}
+}
+
+/// Turns audio into transcripts, such as by streaming it to Deepgram. Transports are kept as
+/// non-send resources, since browser sockets can't leave the main thread.
+pub trait TranscriptionTransport: FromWorld + 'static {
+    /// Add any resources and systems the transport needs, e.g. to manage its connection.
+    fn add_systems(app: &mut App);
+
+    /// Queue audio to be transcribed.
+    fn push_audio(&mut self, audio: AudioBuffer);
+
+    /// The next transcript, if any has arrived since the last call.
+    fn try_transcript(&mut self) -> Option<Transcript>;
+
+    /// How many buffers of audio have been sent on, and how many were dropped because they
+    /// couldn't be sent in time.
+    fn counts(&self) -> QueueCounts {
+        QueueCounts::default()
+    }
+
+    /// Audio the transport has finished with, to hand back to the source.
+    fn recycled_audio(&mut self) -> Option<AudioBuffer> {
+        None
+    }
+}
+
+/// Decides which keywords a transcript contains.
+pub trait TranscriptMapper: FromWorld + Send + Sync + 'static {
+    /// The speech events for a transcript. These are sent as tentative speech events unless the
+    /// transcript is final.
+    fn map(
+        &mut self,
+        transcript: &Transcript,
+        keyword_matcher: &KeywordMatcher,
+    ) -> Vec<SpeechEvent>;
+}
+
+/// A complete way of hearing the player.
+pub trait SpeechBackend: Send + Sync + 'static {
+    type Source: AudioSource;
+    type Transport: TranscriptionTransport;
+    type Mapper: TranscriptMapper;
+}
+
+/// The state of the connection to the transcription service, for any system or UI that wants to
+/// know whether the game can hear the player right now. Transports that don't connect to anything
+/// just leave it `Open`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpeechConnectionStatus {
+    /// Waiting for the socket to open, or for what we need to open it.
+    #[default]
+    Connecting,
+    Open,
+    /// Closed normally. We'll reconnect shortly.
+    Closed,
+    /// Closed abnormally or never opened. We'll retry with an increasing delay.
+    Failed(String),
+}
+
+/// Something that stopped the game hearing the player. These are sent as events and logged, and
+/// the game carries on without speech until the problem clears up, if it does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpeechError {
+    /// The transcription service didn't accept our credentials.
+    BadCredentials,
+    /// The connection to the transcription service couldn't be opened, or was lost.
+    Socket(String),
+    /// Audio or a control message couldn't be sent.
+    Send(String),
+    /// No audio has come from the microphone, e.g. because the player hasn't given permission to
+    /// use it, or there isn't one.
+    MicrophoneUnavailable,
+    /// Something we were reading from has gone away for good.
+    ChannelClosed(&'static str),
+}
+
+/// Shown to the player, so it says what went wrong rather than how.
+impl fmt::Display for SpeechError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeechError::BadCredentials => write!(f, "Deepgram didn't accept the API key"),
+            SpeechError::Socket(reason) => write!(f, "Can't reach Deepgram: {}", reason),
+            SpeechError::Send(reason) => write!(f, "Couldn't send audio to Deepgram: {}", reason),
+            SpeechError::MicrophoneUnavailable => write!(f, "Can't hear the microphone"),
+            SpeechError::ChannelClosed(what) => write!(f, "The {} stopped working", what),
+        }
+    }
+}
+
+/// If no audio has arrived this long after startup, the microphone probably isn't available.
+const MICROPHONE_TIMEOUT_SECONDS: f64 = 5.0;
+/// If no audio has arrived for this long, the microphone has stopped since it last sent any.
+pub(crate) const AUDIO_STALE_SECONDS: f64 = 1.0;
+
+/// How loud the audio going to the transport is, for level meters. Samples are between 0 and 1.
+#[derive(Clone, Debug, Default)]
+pub struct AudioLevel {
+    pub rms: f32,
+    pub peak: f32,
+    /// When audio last arrived from the source, in seconds since startup, if it ever has.
+    pub last_audio: Option<f64>,
+}
+
+/// How far behind each stage of the speech pipeline can fall before the oldest audio waiting in it
+/// is thrown away. Insert this resource before adding the `SpeechPlugin` to change it.
+#[derive(Clone, Debug)]
+pub struct SpeechLatencySettings {
+    /// How much audio can wait between the source and the game, e.g. while a frame takes a long
+    /// time.
+    pub max_source_latency_seconds: f32,
+    /// How much audio can wait to be sent by the transport, e.g. while it reconnects. Words
+    /// spoken during a short drop-out are still heard, but not minutes late.
+    pub max_transport_latency_seconds: f32,
+}
+
+impl Default for SpeechLatencySettings {
+    fn default() -> Self {
+        SpeechLatencySettings {
+            max_source_latency_seconds: 1.0,
+            max_transport_latency_seconds: 10.0,
+        }
+    }
+}
+
+/// Counts of what made it through a queue, and what was thrown away to keep it short.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueCounts {
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+/// How many buffers of audio made it through the speech pipeline, and how many were dropped on
+/// the way, for spotting a pipeline that can't keep up.
+#[derive(Clone, Debug, Default)]
+pub struct SpeechDiagnostics {
+    /// Buffers read from the audio source.
+    pub source: QueueCounts,
+    /// Buffers sent on by the transport, e.g. to Deepgram.
+    pub transport: QueueCounts,
+}
+
+/// Listens to the player with the backend `B`, sending `SpeechEvent`s and
+/// `TentativeSpeechEvent`s for the keywords they say.
+pub struct SpeechPlugin<B>(PhantomData<fn() -> B>);
+
+impl<B> Default for SpeechPlugin<B> {
+    fn default() -> Self {
+        SpeechPlugin(PhantomData)
+    }
+}
+
+impl<B: SpeechBackend> Plugin for SpeechPlugin<B> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TranscriptEvent>()
+            .add_event::<SpeechError>()
+            .init_resource::<SpeechConnectionStatus>()
+            .init_resource::<AudioLevel>()
+            .init_resource::<SpeechLatencySettings>()
+            .init_resource::<SpeechDiagnostics>()
+            .init_resource::<B::Source>()
+            .init_non_send_resource::<B::Transport>()
+            .init_resource::<B::Mapper>()
+            .add_system(feed_transport::<B>)
+            .add_system(map_transcripts::<B>)
+            .add_system(update_diagnostics::<B>)
+            .add_system(watch_source::<B>)
+            .add_system(log_speech_errors);
+
+        B::Transport::add_systems(app);
+    }
+}
+
+/// Audio stops abruptly when the player stops transmitting, so we follow it with this much
+/// silence, so that whatever they were saying is finished rather than left waiting for more audio.
+const TRAILING_SILENCE_SECONDS: f32 = 0.5;
+
+#[derive(Default)]
+struct FeedState {
+    /// The sample rate of the last audio we sent.
+    sample_rate: Option<u32>,
+    was_transmitting: bool,
+}
+
+/// Audio is still read from the source while the player isn't transmitting, so that it doesn't
+/// pile up and the level meter keeps working, but it goes no further. The recording gets
+/// everything the player transmits, while the transport only gets what the voice activity
+/// detector, if any, lets through.
+#[allow(clippy::too_many_arguments)]
+fn feed_transport<B: SpeechBackend>(
+    time: Res<Time>,
+    input: Option<Res<SpeechInput>>,
+    mut source: ResMut<B::Source>,
+    mut transport: NonSendMut<B::Transport>,
+    mut level: ResMut<AudioLevel>,
+    mut recording: Option<ResMut<SessionRecording>>,
+    mut voice_activity_detector: Option<ResMut<VoiceActivityDetector>>,
+    mut state: Local<FeedState>,
+) {
+    let transmitting = input.is_none_or(|input| input.transmitting);
+    let mut sum_of_squares = 0.0;
+    let mut sample_count = 0;
+    let mut peak = 0.0f32;
+    let mut transmitted = Vec::new();
+
+    while let Some(audio) = source.try_read() {
+        for &sample in &audio.samples {
+            let sample = sample as f32 / 32768.0;
+            sum_of_squares += sample * sample;
+            peak = peak.max(sample.abs());
+        }
+        sample_count += audio.samples.len();
+        if transmitting {
+            state.sample_rate = Some(audio.sample_rate);
+            transmitted.push(audio);
+        } else {
+            source.recycle(audio);
+        }
+    }
+
+    if state.was_transmitting && !transmitting {
+        if let Some(sample_rate) = state.sample_rate {
+            transmitted.push(AudioBuffer {
+                sample_rate,
+                samples: vec![0; (sample_rate as f32 * TRAILING_SILENCE_SECONDS) as usize],
+            });
+        }
+    }
+    state.was_transmitting = transmitting;
+
+    for audio in transmitted {
+        if let Some(recording) = &mut recording {
+            recording.push_audio(&audio);
+        }
+        match &mut voice_activity_detector {
+            // the detector copies out what it lets through, so the source can have this back
+            Some(voice_activity_detector) => {
+                let speech = voice_activity_detector.process(&audio);
+                source.recycle(audio);
+                if let Some(speech) = speech {
+                    transport.push_audio(speech);
+                }
+            }
+            None => transport.push_audio(audio),
+        }
+    }
+    while let Some(audio) = transport.recycled_audio() {
+        source.recycle(audio);
+    }
+
+    // the meter only changes when there's new audio to measure
+    if sample_count > 0 {
+        level.rms = (sum_of_squares / sample_count as f32).sqrt();
+        level.peak = peak;
+        level.last_audio = Some(time.seconds_since_startup());
+    }
+}
+
+fn update_diagnostics<B: SpeechBackend>(
+    source: Res<B::Source>,
+    transport: NonSend<B::Transport>,
+    mut diagnostics: ResMut<SpeechDiagnostics>,
+) {
+    let source_counts = source.counts();
+    let transport_counts = transport.counts();
+    if source_counts.dropped > diagnostics.source.dropped {
+        warn!(
+            "Dropped {} buffers of audio that the game didn't read in time.",
+            source_counts.dropped - diagnostics.source.dropped
+        );
+    }
+    if source_counts != diagnostics.source || transport_counts != diagnostics.transport {
+        diagnostics.source = source_counts;
+        diagnostics.transport = transport_counts;
+    }
+}
+
+/// Let the player know if the source never gets going, or stops for good.
+fn watch_source<B: SpeechBackend>(
+    time: Res<Time>,
+    source: Res<B::Source>,
+    level: Res<AudioLevel>,
+    mut errors: EventWriter<SpeechError>,
+    mut reported: Local<bool>,
+) {
+    if *reported {
+        return;
+    }
+    if source.is_closed() {
+        errors.send(SpeechError::ChannelClosed("microphone"));
+        *reported = true;
+    } else if level.last_audio.is_none()
+        && time.seconds_since_startup() > MICROPHONE_TIMEOUT_SECONDS
+    {
+        errors.send(SpeechError::MicrophoneUnavailable);
+        *reported = true;
+    }
+}
+
+fn log_speech_errors(mut errors: EventReader<SpeechError>) {
+    for error in errors.iter() {
+        warn!("Speech isn't working: {}.", error);
+    }
+}
+
+/// Transcripts wait in the transport until the vocabulary has loaded.
+fn map_transcripts<B: SpeechBackend>(
+    mut transport: NonSendMut<B::Transport>,
+    mut mapper: ResMut<B::Mapper>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    mut tentative_speech_events: EventWriter<TentativeSpeechEvent>,
+    mut speech_events: EventWriter<SpeechEvent>,
+    mut transcript_events: EventWriter<TranscriptEvent>,
+) {
+    let keyword_matcher = match keyword_matcher {
+        Some(keyword_matcher) => keyword_matcher,
+        None => return,
+    };
+
+    while let Some(transcript) = transport.try_transcript() {
+        for speech_event in mapper.map(&transcript, &keyword_matcher) {
+            if transcript.is_final {
+                info!(
+                    "Sending {:?} speech event (confidence {}, in {:?}).",
+                    speech_event.keyword, speech_event.confidence, speech_event.transcript
+                );
+                speech_events.send(speech_event);
+            } else {
+                debug!(
+                    "Sending tentative {:?} speech event (confidence {}, in {:?}).",
+                    speech_event.keyword, speech_event.confidence, speech_event.transcript
+                );
+                tentative_speech_events.send(TentativeSpeechEvent(speech_event));
+            }
+        }
+        transcript_events.send(TranscriptEvent(transcript));
+    }
+}
+
+/// Matches the words of each transcript against the vocabulary. Keywords in interim transcripts
+/// become tentative speech events and keywords in final transcripts speech events, each at most
+/// once per word.
+#[derive(Default)]
+pub struct KeywordMapper {
+    tentative: HitLog,
+    committed: HitLog,
+    /// The stream the hits were heard in, since each stream's timings start again from zero.
+    stream: u32,
+}
+
+impl TranscriptMapper for KeywordMapper {
+    fn map(
+        &mut self,
+        transcript: &Transcript,
+        keyword_matcher: &KeywordMatcher,
+    ) -> Vec<SpeechEvent> {
+        if transcript.stream != self.stream {
+            self.stream = transcript.stream;
+            self.tentative.clear();
+            self.committed.clear();
+        }
+
+        let hits = if transcript.is_final {
+            &mut self.committed
+        } else {
+            &mut self.tentative
+        };
+
+        keyword_speech_events(transcript, keyword_matcher)
+            .into_iter()
+            .filter(|speech_event| hits.insert(speech_event))
+            .collect()
+    }
+}
+
+/// Keyword hits we've already sent, so that a word isn't sent again each time an interim result
+/// revises the transcript around it.
+#[derive(Default)]
+struct HitLog(Vec<Hit>);
+
+struct Hit {
+    keyword: Keyword,
+    start: f32,
+    end: f32,
+}
+
+/// Interim results may nudge a word's timing a little between revisions.
+const HIT_TIMING_TOLERANCE: f32 = 0.1;
+/// How far back in the audio stream we remember hits for.
+const HIT_MEMORY_SECONDS: f32 = 60.0;
+
+impl HitLog {
+    /// Remember a hit, returning `false` if it was already sent.
+    fn insert(&mut self, event: &SpeechEvent) -> bool {
+        let already_sent = self.0.iter().any(|hit| {
+            hit.keyword == event.keyword
+                && hit.start < event.end + HIT_TIMING_TOLERANCE
+                && event.start < hit.end + HIT_TIMING_TOLERANCE
+        });
+        if already_sent {
+            return false;
+        }
+
+        self.0
+            .retain(|hit| hit.end > event.start - HIT_MEMORY_SECONDS);
+        self.0.push(Hit {
+            keyword: event.keyword,
+            start: event.start,
+            end: event.end,
+        });
+        true
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Only the words of the transcript are matched, so that request ids, model names and other
+/// metadata can't trigger a puzzle.
+pub fn keyword_speech_events(
+    transcript: &Transcript,
+    keyword_matcher: &KeywordMatcher,
+) -> Vec<SpeechEvent> {
+    let words: Vec<&str> = transcript.words.iter().map(|w| w.word.as_str()).collect();
+    keyword_matcher
+        .find_matches(&words)
+        .into_iter()
+        .filter_map(|keyword_match| {
+            let keyword = Keyword::from_name(keyword_match.keyword)?;
+            trace!("{:?} match for {:?}.", keyword_match.kind, keyword);
+
+            // a multi-word alias is only as confident as its least confident word
+            let matched_words =
+                &transcript.words[keyword_match.word_index..][..keyword_match.word_count];
+            Some(SpeechEvent {
+                keyword,
+                confidence: matched_words
+                    .iter()
+                    .map(|word| word.confidence)
+                    .fold(1.0, f32::min),
+                start: matched_words[0].start,
+                end: matched_words[matched_words.len() - 1].end,
+                transcript: transcript.text.clone(),
+                source: SpeechSource::Voice,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keywords::Vocabulary;
+
+    fn keyword_matcher() -> KeywordMatcher {
+        let vocabulary: Vocabulary =
+            serde_json::from_str(include_str!("../assets/puzzle_words.vocab.json"))
+                .expect("the vocabulary should parse");
+        KeywordMatcher::new(&vocabulary)
+    }
+
+    fn sugar(start: f32, is_final: bool, stream: u32) -> Transcript {
+        Transcript {
+            text: "sugar".to_string(),
+            words: vec![TranscriptWord {
+                word: "sugar".to_string(),
+                start,
+                end: start + 0.5,
+                confidence: 0.99,
+            }],
+            is_final,
+            stream,
+        }
+    }
+
+    #[test]
+    fn each_word_is_sent_once() {
+        let keyword_matcher = keyword_matcher();
+        let mut mapper = KeywordMapper::default();
+
+        assert_eq!(mapper.map(&sugar(1.0, false, 0), &keyword_matcher).len(), 1);
+        assert!(mapper
+            .map(&sugar(1.05, false, 0), &keyword_matcher)
+            .is_empty());
+        assert_eq!(mapper.map(&sugar(1.0, true, 0), &keyword_matcher).len(), 1);
+        assert!(mapper
+            .map(&sugar(1.0, true, 0), &keyword_matcher)
+            .is_empty());
+        assert_eq!(mapper.map(&sugar(3.0, true, 0), &keyword_matcher).len(), 1);
+    }
+
+    #[test]
+    fn hits_are_forgotten_when_a_new_stream_starts() {
+        let keyword_matcher = keyword_matcher();
+        let mut mapper = KeywordMapper::default();
+
+        assert_eq!(mapper.map(&sugar(1.0, false, 0), &keyword_matcher).len(), 1);
+        assert_eq!(mapper.map(&sugar(1.0, true, 0), &keyword_matcher).len(), 1);
+        // the new stream's timings start from zero again, overlapping the old stream's
+        assert_eq!(mapper.map(&sugar(1.0, false, 1), &keyword_matcher).len(), 1);
+        assert_eq!(mapper.map(&sugar(1.0, true, 1), &keyword_matcher).len(), 1);
+    }
+}
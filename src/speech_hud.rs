@@ -0,0 +1,330 @@
+//! A level meter and status indicators for the speech pipeline, so that players can see whether
+//! the game can hear them at all.
+use super::speech::{
+    AudioLevel, SpeechConnectionStatus, SpeechError, TranscriptEvent, AUDIO_STALE_SECONDS,
+};
+use super::speech_input::{SpeechInput, SpeechInputSettings};
+use super::vad::VoiceActivityDetector;
+use bevy::prelude::*;
+
+/// The quietest level the meter shows, in decibels relative to full scale.
+const METER_FLOOR_DB: f32 = -60.0;
+/// How quickly the meter falls back, in fractions of its length per second. It rises instantly.
+const METER_FALL_RATE: f32 = 1.5;
+const METER_WIDTH: f32 = 60.0;
+const METER_HEIGHT: f32 = 8.0;
+const ERROR_WIDTH: f32 = 360.0;
+/// Samples this loud have probably been clipped.
+const CLIPPING_PEAK: f32 = 0.99;
+/// How long the meter stays red after clipping.
+const CLIPPING_HOLD_SECONDS: f64 = 0.5;
+
+const GOOD_COLOR: Color = Color::GREEN;
+const WAITING_COLOR: Color = Color::YELLOW;
+const BAD_COLOR: Color = Color::RED;
+const UNKNOWN_COLOR: Color = Color::GRAY;
+
+pub struct SpeechHudPlugin;
+
+impl Plugin for SpeechHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpeechHudState>()
+            .add_startup_system(spawn_speech_hud)
+            .add_system(update_speech_hud_state)
+            .add_system(show_microphone_status)
+            .add_system(show_level_meter)
+            .add_system(show_connection_status)
+            .add_system(show_input_mode)
+            .add_system(show_error)
+            .add_system(show_activity);
+    }
+}
+
+#[derive(Default)]
+struct SpeechHudState {
+    /// Where the meter is drawn up to, from 0 to 1.
+    meter: f32,
+    /// When the audio last clipped, in seconds since startup.
+    clipped_at: Option<f64>,
+    /// Whether the recognizer is partway through an utterance, i.e. it has sent interim words
+    /// that it hasn't finalized yet.
+    processing: bool,
+    /// The latest thing to go wrong, until it's put right.
+    error: Option<SpeechError>,
+}
+
+#[derive(Component)]
+struct InputModeText;
+
+#[derive(Component)]
+struct MicrophoneIcon;
+
+#[derive(Component)]
+struct MeterFill;
+
+#[derive(Component)]
+struct ConnectionIcon;
+
+#[derive(Component)]
+struct ActivityText;
+
+#[derive(Component)]
+struct ErrorText;
+
+fn spawn_speech_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load("kongtext.ttf"),
+        font_size: 8.0,
+        color: UNKNOWN_COLOR,
+    };
+    let spacing = Style {
+        margin: UiRect {
+            left: Val::Px(4.0),
+            ..default()
+        },
+        ..default()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    bottom: Val::Px(24.0),
+                    ..default()
+                },
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section("", style.clone()))
+                .insert(InputModeText);
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section("MIC", style.clone()).with_style(spacing.clone()),
+                )
+                .insert(MicrophoneIcon);
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(METER_WIDTH), Val::Px(METER_HEIGHT)),
+                        ..spacing.clone()
+                    },
+                    color: Color::rgba(1.0, 1.0, 1.0, 0.2).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..default()
+                            },
+                            color: GOOD_COLOR.into(),
+                            ..default()
+                        })
+                        .insert(MeterFill);
+                });
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section("NET", style.clone()).with_style(spacing.clone()),
+                )
+                .insert(ConnectionIcon);
+            parent
+                .spawn_bundle(TextBundle::from_section("", style.clone()).with_style(spacing))
+                .insert(ActivityText);
+        });
+
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    color: BAD_COLOR,
+                    ..style
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    bottom: Val::Px(40.0),
+                    ..default()
+                },
+                max_size: Size {
+                    width: Val::Px(ERROR_WIDTH),
+                    height: Val::Auto,
+                },
+                ..default()
+            }),
+        )
+        .insert(ErrorText);
+}
+
+fn update_speech_hud_state(
+    time: Res<Time>,
+    level: Res<AudioLevel>,
+    status: Res<SpeechConnectionStatus>,
+    mut state: ResMut<SpeechHudState>,
+    mut transcript_events: EventReader<TranscriptEvent>,
+    mut errors: EventReader<SpeechError>,
+) {
+    let db = 20.0 * level.rms.max(1e-6).log10();
+    let target = (1.0 - db / METER_FLOOR_DB).clamp(0.0, 1.0);
+    let fallen = state.meter - METER_FALL_RATE * time.delta_seconds();
+    state.meter = target.max(fallen).max(0.0);
+
+    if level.peak >= CLIPPING_PEAK && level.is_changed() {
+        state.clipped_at = Some(time.seconds_since_startup());
+    }
+
+    for TranscriptEvent(transcript) in transcript_events.iter() {
+        state.processing = !transcript.is_final && !transcript.words.is_empty();
+    }
+
+    if let Some(error) = errors.iter().last() {
+        state.error = Some(error.clone());
+    }
+    let hearing = level
+        .last_audio
+        .is_some_and(|last_audio| time.seconds_since_startup() - last_audio < AUDIO_STALE_SECONDS);
+    let put_right = match &state.error {
+        Some(SpeechError::MicrophoneUnavailable | SpeechError::ChannelClosed(_)) => hearing,
+        Some(_) => *status == SpeechConnectionStatus::Open,
+        None => false,
+    };
+    if put_right {
+        state.error = None;
+    }
+}
+
+/// The browser doesn't tell us whether it was given permission to use the microphone, so we go
+/// by whether any audio has arrived: grey until it does, then green while it keeps arriving, and
+/// red if it stops.
+fn show_microphone_status(
+    time: Res<Time>,
+    level: Res<AudioLevel>,
+    mut text_query: Query<&mut Text, With<MicrophoneIcon>>,
+) {
+    let color = match level.last_audio {
+        None => UNKNOWN_COLOR,
+        Some(last_audio) if time.seconds_since_startup() - last_audio < AUDIO_STALE_SECONDS => {
+            GOOD_COLOR
+        }
+        Some(_) => BAD_COLOR,
+    };
+    text_query.single_mut().sections[0].style.color = color;
+}
+
+/// The meter is greyed out while the voice activity detector doesn't think the player is
+/// speaking, since that audio isn't being sent on.
+fn show_level_meter(
+    time: Res<Time>,
+    state: Res<SpeechHudState>,
+    voice_activity_detector: Option<Res<VoiceActivityDetector>>,
+    mut fill_query: Query<(&mut Style, &mut UiColor), With<MeterFill>>,
+) {
+    let (mut style, mut color) = fill_query.single_mut();
+    style.size.width = Val::Percent(state.meter * 100.0);
+
+    let clipping = state.clipped_at.is_some_and(|clipped_at| {
+        time.seconds_since_startup() - clipped_at < CLIPPING_HOLD_SECONDS
+    });
+    let speaking = voice_activity_detector.is_none_or(|detector| detector.is_speaking());
+    color.0 = if clipping {
+        BAD_COLOR
+    } else if speaking {
+        GOOD_COLOR
+    } else {
+        UNKNOWN_COLOR
+    };
+}
+
+fn show_connection_status(
+    status: Res<SpeechConnectionStatus>,
+    mut text_query: Query<&mut Text, With<ConnectionIcon>>,
+) {
+    if !status.is_changed() {
+        return;
+    }
+    text_query.single_mut().sections[0].style.color = match *status {
+        SpeechConnectionStatus::Open => GOOD_COLOR,
+        SpeechConnectionStatus::Connecting | SpeechConnectionStatus::Closed => WAITING_COLOR,
+        SpeechConnectionStatus::Failed(_) => BAD_COLOR,
+    };
+}
+
+/// The input mode is lit up while the player's audio is being sent on, e.g. while they hold the
+/// push-to-talk key.
+fn show_input_mode(
+    settings: Option<Res<SpeechInputSettings>>,
+    input: Option<Res<SpeechInput>>,
+    mut text_query: Query<&mut Text, With<InputModeText>>,
+) {
+    let (settings, input) = match (settings, input) {
+        (Some(settings), Some(input)) => (settings, input),
+        _ => return,
+    };
+    if !settings.is_changed() && !input.is_changed() {
+        return;
+    }
+
+    let mut text = text_query.single_mut();
+    text.sections[0].value = settings.mode.name().to_uppercase();
+    text.sections[0].style.color = if input.transmitting {
+        GOOD_COLOR
+    } else {
+        UNKNOWN_COLOR
+    };
+}
+
+/// What went wrong, and that the puzzles can still be solved without speech.
+fn show_error(state: Res<SpeechHudState>, mut text_query: Query<&mut Text, With<ErrorText>>) {
+    if !state.is_changed() {
+        return;
+    }
+    let message = match &state.error {
+        Some(error @ (SpeechError::MicrophoneUnavailable | SpeechError::ChannelClosed(_))) => {
+            format!("{}. Press Enter to type a puzzle word instead.", error)
+        }
+        Some(error) => format!("{}. Press J, B or M to say a puzzle word instead.", error),
+        None => String::new(),
+    };
+    let mut text = text_query.single_mut();
+    if text.sections[0].value != message {
+        text.sections[0].value = message;
+    }
+}
+
+/// Whether the game is listening for speech, or working out what was just said, or whether the
+/// player has muted themselves.
+fn show_activity(
+    time: Res<Time>,
+    level: Res<AudioLevel>,
+    status: Res<SpeechConnectionStatus>,
+    state: Res<SpeechHudState>,
+    input: Option<Res<SpeechInput>>,
+    mut text_query: Query<&mut Text, With<ActivityText>>,
+) {
+    let transmitting = input.is_none_or(|input| input.transmitting);
+    let hearing = level
+        .last_audio
+        .is_some_and(|last_audio| time.seconds_since_startup() - last_audio < AUDIO_STALE_SECONDS);
+    let (activity, color) = match (hearing, &*status) {
+        _ if !transmitting => ("muted", UNKNOWN_COLOR),
+        (true, SpeechConnectionStatus::Open) if state.processing => ("processing", WAITING_COLOR),
+        (true, SpeechConnectionStatus::Open) => ("listening", GOOD_COLOR),
+        _ => ("not listening", BAD_COLOR),
+    };
+
+    let mut text = text_query.single_mut();
+    if text.sections[0].value != activity {
+        text.sections[0].value = activity.to_string();
+    }
+    text.sections[0].style.color = color;
+}
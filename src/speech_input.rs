@@ -0,0 +1,131 @@
+//! Whether the player's audio is sent on for transcription at all: all the time, only while a
+//! key is held, or until they mute it. Not sending audio saves on transcription, and stops
+//! background chatter from setting off puzzles.
+use bevy::prelude::*;
+
+/// Cycles through the input modes, for trying them out.
+const MODE_KEY: KeyCode = KeyCode::F4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// Send everything the microphone hears.
+    AlwaysOn,
+    /// Only send audio while the talk key or button is held.
+    PushToTalk,
+    /// Send audio until the talk key or button is pressed, then nothing until it's pressed again.
+    ToggleMute,
+}
+
+impl InputMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            InputMode::AlwaysOn => "open mic",
+            InputMode::PushToTalk => "push to talk",
+            InputMode::ToggleMute => "toggle mute",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            InputMode::AlwaysOn => InputMode::PushToTalk,
+            InputMode::PushToTalk => InputMode::ToggleMute,
+            InputMode::ToggleMute => InputMode::AlwaysOn,
+        }
+    }
+}
+
+/// How the player controls their microphone. Insert this resource before adding the
+/// `SpeechInputPlugin` to change it. The mode can also be cycled with `MODE_KEY`.
+#[derive(Clone, Debug)]
+pub struct SpeechInputSettings {
+    pub mode: InputMode,
+    /// Held for push-to-talk, pressed to mute and unmute.
+    pub talk_key: KeyCode,
+    /// The same as `talk_key`, on any gamepad.
+    pub talk_button: Option<GamepadButtonType>,
+}
+
+impl Default for SpeechInputSettings {
+    fn default() -> Self {
+        SpeechInputSettings {
+            mode: InputMode::AlwaysOn,
+            talk_key: KeyCode::Space,
+            talk_button: Some(GamepadButtonType::South),
+        }
+    }
+}
+
+/// Whether audio is being sent on right now. The speech plugin drops audio while this is
+/// `false`, and the transport keeps its connection alive in the meantime.
+#[derive(Clone, Debug)]
+pub struct SpeechInput {
+    pub transmitting: bool,
+    /// Whether the player has muted themselves in `InputMode::ToggleMute`.
+    muted: bool,
+}
+
+impl Default for SpeechInput {
+    fn default() -> Self {
+        SpeechInput {
+            transmitting: true,
+            muted: false,
+        }
+    }
+}
+
+pub struct SpeechInputPlugin;
+
+impl Plugin for SpeechInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpeechInputSettings>()
+            .init_resource::<SpeechInput>()
+            .add_system(cycle_input_mode)
+            .add_system(update_speech_input.after(cycle_input_mode));
+    }
+}
+
+fn cycle_input_mode(keys: Res<Input<KeyCode>>, mut settings: ResMut<SpeechInputSettings>) {
+    if keys.just_pressed(MODE_KEY) {
+        settings.mode = settings.mode.next();
+        info!("Speech input mode is now {}.", settings.mode.name());
+    }
+}
+
+fn update_speech_input(
+    settings: Res<SpeechInputSettings>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    mut input: ResMut<SpeechInput>,
+) {
+    let gamepad_buttons = || {
+        settings.talk_button.into_iter().flat_map(|button_type| {
+            gamepads.iter().map(move |&gamepad| GamepadButton {
+                gamepad,
+                button_type,
+            })
+        })
+    };
+    let held =
+        keys.pressed(settings.talk_key) || gamepad_buttons().any(|button| buttons.pressed(button));
+    let pressed = keys.just_pressed(settings.talk_key)
+        || gamepad_buttons().any(|button| buttons.just_pressed(button));
+
+    if settings.mode == InputMode::ToggleMute && pressed {
+        input.muted = !input.muted;
+        info!(
+            "Microphone {}.",
+            if input.muted { "muted" } else { "unmuted" }
+        );
+    }
+
+    let transmitting = match settings.mode {
+        InputMode::AlwaysOn => true,
+        InputMode::PushToTalk => held,
+        InputMode::ToggleMute => !input.muted,
+    };
+    // only touch the resource when it changes, so that systems can watch for that
+    if input.transmitting != transmitting {
+        input.transmitting = transmitting;
+    }
+}
@@ -0,0 +1,195 @@
+//! Typing words in place of saying them, for when the game can't hear the microphone at all. Typed
+//! words go through the same keyword matching as spoken ones, so the puzzles can't tell the
+//! difference.
+use super::keywords::KeywordMatcher;
+use super::speech::{
+    keyword_speech_events, AudioLevel, SpeechError, Transcript, TranscriptEvent,
+    AUDIO_STALE_SECONDS,
+};
+use super::{SpeechEvent, SpeechSource};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+
+const START_KEY: KeyCode = KeyCode::Return;
+const SUBMIT_KEY: KeyCode = KeyCode::Return;
+const DELETE_KEY: KeyCode = KeyCode::Back;
+const CANCEL_KEY: KeyCode = KeyCode::Escape;
+/// Puzzle words are short, so there's no need for more than a few of them at once.
+const MAX_TYPED_LENGTH: usize = 40;
+
+const PROMPT_COLOR: Color = Color::GRAY;
+const TYPING_COLOR: Color = Color::WHITE;
+
+pub struct TypedInputPlugin;
+
+/// Takes the keyboard while the player is typing, before anything else sees it.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, SystemLabel)]
+pub struct TypedInputSystem;
+
+impl Plugin for TypedInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TypedInput>()
+            .add_startup_system(spawn_typing_box)
+            .add_system(toggle_typed_input)
+            // the keys typed into the box mustn't also move the player, so they're taken before
+            // any other system sees them
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                type_words.label(TypedInputSystem).after(InputSystem),
+            )
+            .add_system(show_typing_box);
+    }
+}
+
+/// Whether the player can type words, and what they've typed so far.
+#[derive(Default)]
+pub struct TypedInput {
+    /// Turned on when the game can't hear the microphone, and off again once it can.
+    pub enabled: bool,
+    /// Whether the box has the keyboard.
+    typing: bool,
+    text: String,
+}
+
+#[derive(Component)]
+struct TypingBox;
+
+#[derive(Component)]
+struct TypingText;
+
+fn spawn_typing_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    right: Val::Px(8.0),
+                    top: Val::Px(8.0),
+                    ..default()
+                },
+                padding: UiRect::all(Val::Px(4.0)),
+                ..default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(TypingBox)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("kongtext.ttf"),
+                        font_size: 12.0,
+                        color: PROMPT_COLOR,
+                    },
+                ))
+                .insert(TypingText);
+        });
+}
+
+fn toggle_typed_input(
+    time: Res<Time>,
+    level: Res<AudioLevel>,
+    mut input: ResMut<TypedInput>,
+    mut errors: EventReader<SpeechError>,
+) {
+    let microphone_failed = errors.iter().any(|error| {
+        matches!(
+            error,
+            SpeechError::MicrophoneUnavailable | SpeechError::ChannelClosed(_)
+        )
+    });
+    // the same test the speech HUD uses to clear the error, so the box goes away along with it
+    let hearing = level
+        .last_audio
+        .is_some_and(|last_audio| time.seconds_since_startup() - last_audio < AUDIO_STALE_SECONDS);
+    if microphone_failed && !input.enabled {
+        info!("Can't hear the microphone, so words can be typed instead.");
+        input.enabled = true;
+    } else if hearing && input.enabled {
+        info!("Hearing the microphone again, so words can't be typed any more.");
+        *input = TypedInput::default();
+    }
+}
+
+fn type_words(
+    mut input: ResMut<TypedInput>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    keyword_matcher: Option<Res<KeywordMatcher>>,
+    mut speech_events: EventWriter<SpeechEvent>,
+    mut transcript_events: EventWriter<TranscriptEvent>,
+) {
+    if !input.enabled {
+        return;
+    }
+    if !input.typing {
+        if keys.just_pressed(START_KEY) {
+            input.typing = true;
+            keys.reset(START_KEY);
+        }
+        // the characters typed while the box didn't have the keyboard weren't meant for it
+        characters.clear();
+        return;
+    }
+
+    for character in characters.iter() {
+        if !character.char.is_control() && input.text.len() < MAX_TYPED_LENGTH {
+            input.text.push(character.char);
+        }
+    }
+    if keys.just_pressed(DELETE_KEY) {
+        input.text.pop();
+    }
+    if keys.just_pressed(CANCEL_KEY) {
+        input.typing = false;
+        input.text.clear();
+    }
+    if keys.just_pressed(SUBMIT_KEY) {
+        input.typing = false;
+        let text = std::mem::take(&mut input.text);
+        match &keyword_matcher {
+            Some(keyword_matcher) => {
+                let transcript = Transcript::typed(&text);
+                for mut speech_event in keyword_speech_events(&transcript, keyword_matcher) {
+                    speech_event.source = SpeechSource::Typed;
+                    info!(
+                        "Sending {:?} speech event typed as {:?}.",
+                        speech_event.keyword, speech_event.transcript
+                    );
+                    speech_events.send(speech_event);
+                }
+                transcript_events.send(TranscriptEvent(transcript));
+            }
+            None => warn!(
+                "Ignoring {:?}, since the vocabulary hasn't loaded yet.",
+                text
+            ),
+        }
+    }
+
+    // nothing else gets to see the keys while the player is typing
+    keys.reset_all();
+}
+
+fn show_typing_box(
+    input: Res<TypedInput>,
+    mut box_query: Query<&mut Visibility, With<TypingBox>>,
+    mut text_query: Query<&mut Text, With<TypingText>>,
+) {
+    if !input.is_changed() {
+        return;
+    }
+    box_query.single_mut().is_visible = input.enabled;
+
+    let section = &mut text_query.single_mut().sections[0];
+    if input.typing {
+        section.value = format!("> {}_", input.text);
+        section.style.color = TYPING_COLOR;
+    } else {
+        section.value = "Press Enter to type a word".to_string();
+        section.style.color = PROMPT_COLOR;
+    }
+}
@@ -0,0 +1,347 @@
+//! Voice activity detection, so that only audio with speech in it is sent on for transcription.
+//! Silence costs as much to transcribe as speech does, and background noise can be heard as
+//! words.
+//!
+//! Each 10 ms frame counts as speech if it's clearly louder than the background noise, and its
+//! zero-crossing rate isn't so high that it's more likely hiss or a fan. Audio from just before
+//! speech starts and just after it stops is sent too, so that soft word onsets and endings aren't
+//! clipped.
+use super::speech::AudioBuffer;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const FRAME_SECONDS: f32 = 0.01;
+/// Frames quieter than this are never speech, however quiet the room.
+const MIN_SPEECH_DB: f32 = -50.0;
+/// Frames this far above the noise are speech whatever their zero-crossing rate, since hiss is
+/// rarely that loud.
+const LOUD_ABOVE_NOISE_DB: f32 = 25.0;
+/// How quickly the noise estimate follows quieter and louder frames, as for the offline
+/// keyword spotter's segmenter.
+const NOISE_FALL_RATE: f32 = 0.2;
+const NOISE_RISE_RATE: f32 = 0.005;
+/// This many speech frames in a row open the gate, so that clicks and bumps don't.
+const ONSET_FRAMES: usize = 3;
+
+/// How the voice activity detector decides what's speech. Insert this resource before adding the
+/// `VadPlugin` to change it.
+#[derive(Clone, Debug)]
+pub struct VadSettings {
+    /// Whether to detect voice activity at all. If not, all the audio is sent on.
+    pub enabled: bool,
+    /// How much audio from before speech starts is sent along with it.
+    pub pre_roll_seconds: f32,
+    /// How long after speech stops we keep sending audio, in case it starts again. This also
+    /// gives the recognizer some silence to notice the end of what was said.
+    pub hangover_seconds: f32,
+    /// How far above the background noise a frame has to be to count as speech.
+    pub speech_above_noise_db: f32,
+    /// The fraction of samples that can change sign in a frame of speech. Hiss and fan noise
+    /// change sign about half the time.
+    pub max_zero_crossing_rate: f32,
+}
+
+impl Default for VadSettings {
+    fn default() -> Self {
+        VadSettings {
+            enabled: true,
+            pre_roll_seconds: 0.3,
+            hangover_seconds: 0.6,
+            speech_above_noise_db: 10.0,
+            max_zero_crossing_rate: 0.35,
+        }
+    }
+}
+
+/// Adds a `VoiceActivityDetector` if it's enabled in the `VadSettings`.
+pub struct VadPlugin;
+
+impl Plugin for VadPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = app
+            .world
+            .get_resource_or_insert_with(VadSettings::default)
+            .clone();
+        if settings.enabled {
+            app.insert_resource(VoiceActivityDetector::new(settings));
+        }
+    }
+}
+
+/// Gates the audio on its way to the transport, and says whether the player is speaking right now
+/// for anything else that wants to know, like the HUD.
+pub struct VoiceActivityDetector {
+    settings: VadSettings,
+    sample_rate: u32,
+    noise_db: Option<f32>,
+    /// Samples left over that don't yet make a whole frame.
+    partial_frame: Vec<i16>,
+    /// The most recent audio, kept while the gate is closed in case speech starts.
+    pre_roll: VecDeque<i16>,
+    /// Speech frames in a row while the gate is closed.
+    speech_frames: usize,
+    /// Frames since the last speech frame while the gate is open.
+    silent_frames: usize,
+    open: bool,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(settings: VadSettings) -> Self {
+        VoiceActivityDetector {
+            settings,
+            sample_rate: 0,
+            noise_db: None,
+            partial_frame: Vec::new(),
+            pre_roll: VecDeque::new(),
+            speech_frames: 0,
+            silent_frames: 0,
+            open: false,
+        }
+    }
+
+    /// Whether the player is speaking, as far as we can tell. This stays true for the hangover
+    /// after they stop.
+    pub fn is_speaking(&self) -> bool {
+        self.open
+    }
+
+    /// Pass `audio` through the gate, returning whatever of it should be sent on, if anything.
+    /// The returned audio may include some held back from earlier buffers.
+    pub fn process(&mut self, audio: &AudioBuffer) -> Option<AudioBuffer> {
+        if audio.sample_rate != self.sample_rate {
+            *self = VoiceActivityDetector::new(self.settings.clone());
+            self.sample_rate = audio.sample_rate;
+        }
+        let frame_length = ((self.sample_rate as f32 * FRAME_SECONDS) as usize).max(1);
+        let pre_roll_length = (self.sample_rate as f32 * self.settings.pre_roll_seconds) as usize
+            + ONSET_FRAMES * frame_length;
+        let hangover_frames = (self.settings.hangover_seconds / FRAME_SECONDS) as usize;
+
+        let mut samples = std::mem::take(&mut self.partial_frame);
+        samples.extend_from_slice(&audio.samples);
+        let mut frames = samples.chunks_exact(frame_length);
+        let mut passed = Vec::new();
+
+        for frame in &mut frames {
+            let is_speech = self.is_speech(frame);
+
+            if self.open {
+                passed.extend_from_slice(frame);
+                if is_speech {
+                    self.silent_frames = 0;
+                } else {
+                    self.silent_frames += 1;
+                }
+                if self.silent_frames > hangover_frames {
+                    self.open = false;
+                    self.speech_frames = 0;
+                }
+                continue;
+            }
+
+            self.pre_roll.extend(frame);
+            let excess = self.pre_roll.len().saturating_sub(pre_roll_length);
+            self.pre_roll.drain(..excess);
+            if is_speech {
+                self.speech_frames += 1;
+            } else {
+                self.speech_frames = 0;
+            }
+            if self.speech_frames >= ONSET_FRAMES {
+                self.open = true;
+                self.silent_frames = 0;
+                passed.extend(self.pre_roll.drain(..));
+            }
+        }
+        self.partial_frame = frames.remainder().to_vec();
+
+        if passed.is_empty() {
+            return None;
+        }
+        Some(AudioBuffer {
+            sample_rate: self.sample_rate,
+            samples: passed,
+        })
+    }
+
+    /// Classify a frame, and let the noise estimate follow it.
+    fn is_speech(&mut self, frame: &[i16]) -> bool {
+        let mean_square = frame
+            .iter()
+            .map(|&sample| {
+                let sample = sample as f32 / 32768.0;
+                sample * sample
+            })
+            .sum::<f32>()
+            / frame.len() as f32;
+        let energy_db = 10.0 * (mean_square + 1e-10).log10();
+        let crossings = frame
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        let zero_crossing_rate = crossings as f32 / (frame.len() - 1).max(1) as f32;
+
+        let noise_db = *self.noise_db.get_or_insert(energy_db);
+        let above_noise = energy_db - noise_db;
+        let is_speech = energy_db > MIN_SPEECH_DB
+            && above_noise > self.settings.speech_above_noise_db
+            && (zero_crossing_rate <= self.settings.max_zero_crossing_rate
+                || above_noise > LOUD_ABOVE_NOISE_DB);
+
+        let rate = if energy_db < noise_db {
+            NOISE_FALL_RATE
+        } else {
+            NOISE_RISE_RATE
+        };
+        self.noise_db = Some(noise_db + (energy_db - noise_db) * rate);
+        is_speech
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16_000;
+    const FRAME_LENGTH: usize = 160;
+
+    /// Quiet hiss, like a microphone in an empty room, about 60 dB down.
+    const ROOM_NOISE: f32 = 0.0017;
+    /// Louder hiss, like a fan, about 40 dB down.
+    const FAN_NOISE: f32 = 0.017;
+
+    /// Uniform noise between `-amplitude` and `amplitude`, the same every time.
+    fn noise(amplitude: f32, seconds: f32, seed: &mut u32) -> Vec<i16> {
+        (0..(SAMPLE_RATE as f32 * seconds) as usize)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 17;
+                *seed ^= *seed << 5;
+                let uniform = *seed as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                (uniform * amplitude * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    /// A vowel-ish 200 Hz tone, about 13 dB down.
+    fn tone(seconds: f32) -> Vec<i16> {
+        (0..(SAMPLE_RATE as f32 * seconds) as usize)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                ((2.0 * std::f32::consts::PI * 200.0 * t).sin() * 0.3 * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    /// Feed the audio in a frame at a time, returning what came out of each.
+    fn process(vad: &mut VoiceActivityDetector, samples: &[i16]) -> Vec<Option<Vec<i16>>> {
+        samples
+            .chunks(FRAME_LENGTH)
+            .map(|frame| {
+                vad.process(&AudioBuffer {
+                    sample_rate: SAMPLE_RATE,
+                    samples: frame.to_vec(),
+                })
+                .map(|audio| audio.samples)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn silence_stays_gated() {
+        let mut vad = VoiceActivityDetector::new(VadSettings::default());
+        let mut seed = 1;
+
+        let passed = process(&mut vad, &noise(ROOM_NOISE, 3.0, &mut seed));
+        assert!(passed.iter().all(Option::is_none));
+        assert!(!vad.is_speaking());
+
+        let passed = process(&mut vad, &vec![0; SAMPLE_RATE as usize]);
+        assert!(passed.iter().all(Option::is_none));
+        assert!(!vad.is_speaking());
+    }
+
+    #[test]
+    fn speech_opens_the_gate_with_pre_roll() {
+        let settings = VadSettings::default();
+        let pre_roll_length =
+            (SAMPLE_RATE as f32 * settings.pre_roll_seconds) as usize + ONSET_FRAMES * FRAME_LENGTH;
+        let mut vad = VoiceActivityDetector::new(settings);
+        let mut seed = 1;
+
+        let mut audio = noise(ROOM_NOISE, 1.0, &mut seed);
+        assert!(process(&mut vad, &audio).iter().all(Option::is_none));
+
+        let speech = tone(0.5);
+        let passed = process(&mut vad, &speech);
+        // it takes a few frames of speech to be sure, and they're sent along with the pre-roll
+        assert!(passed[..ONSET_FRAMES - 1].iter().all(Option::is_none));
+        audio.extend_from_slice(&speech[..ONSET_FRAMES * FRAME_LENGTH]);
+        assert_eq!(
+            passed[ONSET_FRAMES - 1].as_deref(),
+            Some(&audio[audio.len() - pre_roll_length..])
+        );
+        // then the speech goes straight through
+        for (passed, frame) in passed[ONSET_FRAMES..]
+            .iter()
+            .zip(speech[ONSET_FRAMES * FRAME_LENGTH..].chunks(FRAME_LENGTH))
+        {
+            assert_eq!(passed.as_deref(), Some(frame));
+        }
+        assert!(vad.is_speaking());
+    }
+
+    #[test]
+    fn hangover_holds_the_gate_open() {
+        let settings = VadSettings::default();
+        let hangover_frames = (settings.hangover_seconds / FRAME_SECONDS) as usize;
+        let mut vad = VoiceActivityDetector::new(settings);
+        let mut seed = 1;
+
+        process(&mut vad, &noise(ROOM_NOISE, 1.0, &mut seed));
+        process(&mut vad, &tone(0.5));
+        assert!(vad.is_speaking());
+
+        let passed = process(&mut vad, &noise(ROOM_NOISE, 1.0, &mut seed));
+        // the hangover is sent on, and the frame after it too as the gate closes
+        assert!(passed[..=hangover_frames].iter().all(Option::is_some));
+        assert!(passed[hangover_frames + 1..].iter().all(Option::is_none));
+        assert!(!vad.is_speaking());
+
+        // a short pause within the hangover doesn't close the gate
+        process(&mut vad, &tone(0.5));
+        process(&mut vad, &noise(ROOM_NOISE, 0.3, &mut seed));
+        let passed = process(&mut vad, &tone(0.5));
+        assert!(passed.iter().all(Option::is_some));
+        assert!(vad.is_speaking());
+    }
+
+    #[test]
+    fn hiss_is_not_speech() {
+        let mut vad = VoiceActivityDetector::new(VadSettings::default());
+        let mut seed = 1;
+
+        process(&mut vad, &noise(ROOM_NOISE, 1.0, &mut seed));
+        // the fan is well above the room noise, but changes sign far too often to be speech
+        let passed = process(&mut vad, &noise(FAN_NOISE, 1.0, &mut seed));
+        assert!(passed.iter().all(Option::is_none));
+        assert!(!vad.is_speaking());
+    }
+
+    #[test]
+    fn a_new_sample_rate_starts_over() {
+        let mut vad = VoiceActivityDetector::new(VadSettings::default());
+        let mut seed = 1;
+
+        process(&mut vad, &noise(ROOM_NOISE, 1.0, &mut seed));
+        process(&mut vad, &tone(0.5));
+        assert!(vad.is_speaking());
+
+        // a new microphone starts the detector over
+        vad.process(&AudioBuffer {
+            sample_rate: 48_000,
+            samples: vec![0; 480],
+        });
+        assert!(!vad.is_speaking());
+    }
+}
@@ -0,0 +1,74 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+
+use crate::keywords::{KeywordMatcher, Vocabulary};
+
+/// The puzzle words, their aliases and how fuzzily to match them.
+const VOCABULARY_PATH: &str = "puzzle_words.vocab.json";
+
+pub struct VocabularyPlugin;
+
+impl Plugin for VocabularyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<VocabularyAsset>()
+            .init_asset_loader::<VocabularyLoader>()
+            .add_startup_system(load_vocabulary)
+            .add_system(update_keyword_matcher);
+    }
+}
+
+#[derive(TypeUuid)]
+#[uuid = "6f1b8a3e-5c2d-4e8f-9a71-0d3c2b4e5f60"]
+pub struct VocabularyAsset(pub Vocabulary);
+
+#[derive(Default)]
+struct VocabularyLoader;
+
+impl AssetLoader for VocabularyLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let vocabulary: Vocabulary = serde_json::from_slice(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(VocabularyAsset(vocabulary)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vocab.json"]
+    }
+}
+
+/// We hold on to the handle so the vocabulary stays loaded for the whole game.
+struct VocabularyHandle(Handle<VocabularyAsset>);
+
+fn load_vocabulary(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(VocabularyHandle(asset_server.load(VOCABULARY_PATH)));
+}
+
+/// The `KeywordMatcher` resource only exists once the vocabulary has loaded, and is rebuilt
+/// whenever the vocabulary changes.
+fn update_keyword_matcher(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<VocabularyAsset>>,
+    vocabularies: Res<Assets<VocabularyAsset>>,
+    vocabulary_handle: Res<VocabularyHandle>,
+) {
+    for event in asset_events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle }
+                if *handle == vocabulary_handle.0 =>
+            {
+                if let Some(vocabulary) = vocabularies.get(handle) {
+                    info!("Loaded {} puzzle keywords.", vocabulary.0.keywords.len());
+                    commands.insert_resource(KeywordMatcher::new(&vocabulary.0));
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Plays a recording through the real microphone source, Deepgram transport and keyword mapper,
+//! with the mock listen server standing in for Deepgram, and checks that the puzzles hear "sugar",
+//! even when the game stops listening partway through the word. Also checks that the mock accepts
+//! every way the game can present its credentials.
+#![cfg(all(feature = "deepgram", feature = "mock", not(target_arch = "wasm32")))]
+
+use bevy::ecs::event::{Events, ManualEventReader};
+use bevy::prelude::*;
+use bevy::window::{WindowFocused, WindowId};
+use jamfest::audio_file::{AudioFile, Pacing};
+use jamfest::deepgram::ListenOptions;
+use jamfest::deepgram_mock::{MockServer, LISTEN_PATH};
+use jamfest::deepgram_transport::{CredentialProvider, DeepgramBackend};
+use jamfest::keywords::{KeywordMatcher, Vocabulary};
+use jamfest::microphone::MicrophoneSettings;
+use jamfest::speech::{SpeechLatencySettings, SpeechPlugin};
+use jamfest::{Keyword, SpeechEvent, SpeechSource, TentativeSpeechEvent};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::{HeaderName, HeaderValue};
+
+const API_KEY: &str = "integration-test-key";
+const SAMPLE_RATE: u32 = 16_000;
+/// Longer than connecting and playing the recording could possibly take.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A tone between two silences, which the mock hears as a spoken word.
+fn write_recording(path: &Path, tone_seconds: f32) {
+    let silence = |seconds: f32| vec![0i16; (SAMPLE_RATE as f32 * seconds) as usize];
+    let tone = (0..(SAMPLE_RATE as f32 * tone_seconds) as u32).map(|index| {
+        let t = index as f32 / SAMPLE_RATE as f32;
+        ((2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.3 * 32767.0) as i16
+    });
+
+    let mut samples = silence(0.3);
+    samples.extend(tone);
+    samples.extend(silence(0.5));
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect();
+    std::fs::write(path, bytes).expect("could not write the recording");
+}
+
+fn keyword_matcher() -> KeywordMatcher {
+    let vocabulary = std::fs::read_to_string("assets/puzzle_words.vocab.json")
+        .expect("could not read the vocabulary");
+    let vocabulary: Vocabulary =
+        serde_json::from_str(&vocabulary).expect("could not parse the vocabulary");
+    KeywordMatcher::new(&vocabulary)
+}
+
+/// Each test plays its own recording, since they run at the same time.
+fn recording_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("jamfest-{}-{}.pcm", name, std::process::id()))
+}
+
+/// Just the speech plugin, listening to the recording at `path` through the mock at `address`.
+fn speech_app(address: SocketAddr, path: &Path, pacing: Pacing) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_event::<SpeechEvent>()
+        .add_event::<TentativeSpeechEvent>()
+        .add_event::<WindowFocused>()
+        .insert_resource(MicrophoneSettings {
+            target_sample_rate: Some(SAMPLE_RATE),
+            chunk_seconds: 0.05,
+            audio_file: Some(AudioFile {
+                path: path.to_path_buf(),
+                pacing,
+                raw_sample_rate: SAMPLE_RATE,
+            }),
+        })
+        // a recording played as fast as possible arrives all at once, and none of it should be dropped
+        .insert_resource(SpeechLatencySettings {
+            max_source_latency_seconds: 10.0,
+            max_transport_latency_seconds: 10.0,
+        })
+        .insert_resource(keyword_matcher())
+        .add_plugin(SpeechPlugin::<DeepgramBackend>::default())
+        .insert_resource(
+            ListenOptions::new()
+                .base_url(format!("ws://{}{}", address, LISTEN_PATH))
+                .interim_results(true),
+        )
+        .insert_resource(CredentialProvider::new().api_key(API_KEY));
+    app
+}
+
+/// Run `app` until it sends an `E`, or `TIMEOUT` passes.
+fn next_event<E: Clone + Send + Sync + 'static>(app: &mut App) -> Option<E> {
+    let mut reader = ManualEventReader::<E>::default();
+    let started = Instant::now();
+    loop {
+        app.update();
+        let events = app.world.resource::<Events<E>>();
+        if let Some(event) = reader.iter(events).next() {
+            return Some(event.clone());
+        }
+        if started.elapsed() > TIMEOUT {
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn saying_sugar_sends_a_sugar_speech_event() {
+    let address = start_mock_server();
+
+    let path = recording_path("sugar");
+    write_recording(&path, 0.5);
+
+    let mut app = speech_app(address, &path, Pacing::AsFastAsPossible);
+    let heard = next_event::<SpeechEvent>(&mut app);
+    let _ = std::fs::remove_file(&path);
+
+    let event = heard.expect("no speech event arrived in time");
+    assert_eq!(event.keyword, Keyword::Sugar);
+    assert_eq!(event.source, SpeechSource::Voice);
+    assert_eq!(event.transcript, "sugar");
+    assert!(event.confidence > 0.9, "confidence {}", event.confidence);
+    assert!(event.start < event.end);
+}
+
+/// The game stops listening partway through a word, so the rest of it never reaches the mock, which
+/// only finishes it off because the game closes the stream.
+#[test]
+fn the_last_word_is_heard_after_listening_pauses() {
+    let address = start_mock_server();
+
+    let path = recording_path("paused");
+    write_recording(&path, 2.0);
+
+    let mut app = speech_app(address, &path, Pacing::RealTime);
+    // the interim result means the mock has started hearing the word
+    let tentative = next_event::<TentativeSpeechEvent>(&mut app);
+    app.world.send_event(WindowFocused {
+        id: WindowId::primary(),
+        focused: false,
+    });
+    let heard = next_event::<SpeechEvent>(&mut app);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(tentative.is_some(), "no interim result arrived in time");
+    let event = heard.expect("the final result didn't arrive after pausing");
+    assert_eq!(event.keyword, Keyword::Sugar);
+    assert_eq!(event.source, SpeechSource::Voice);
+}
+
+fn start_mock_server() -> SocketAddr {
+    let server = MockServer::bind("127.0.0.1:0", Some(API_KEY.to_string()), None)
+        .expect("could not start the mock server");
+    let address = server.local_addr().expect("the mock server has no address");
+    thread::spawn(move || server.run());
+    address
+}
+
+/// Open a listen connection with `header` set, returning the subprotocol the mock picked, if any.
+fn handshake(address: SocketAddr, header: &str, value: &str) -> Result<Option<String>, String> {
+    let mut request = format!(
+        "ws://{}{}?encoding=linear16&sample_rate=16000",
+        address, LISTEN_PATH
+    )
+    .into_client_request()
+    .expect("the URL is valid");
+    request.headers_mut().insert(
+        HeaderName::from_bytes(header.as_bytes()).expect("valid header name"),
+        HeaderValue::from_str(value).expect("valid header value"),
+    );
+    let (mut socket, response) =
+        tungstenite::connect(request).map_err(|error| error.to_string())?;
+    let _ = socket.close(None);
+    Ok(response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|protocol| protocol.to_str().ok())
+        .map(str::to_string))
+}
+
+#[test]
+fn the_mock_accepts_api_keys_and_access_tokens() {
+    let address = start_mock_server();
+    assert_eq!(
+        handshake(address, "Authorization", &format!("Token {}", API_KEY)),
+        Ok(None)
+    );
+    assert_eq!(
+        handshake(address, "Authorization", &format!("Bearer {}", API_KEY)),
+        Ok(None)
+    );
+    assert_eq!(
+        handshake(
+            address,
+            "Sec-WebSocket-Protocol",
+            &format!("token, {}", API_KEY)
+        ),
+        Ok(Some("token".to_string()))
+    );
+    assert_eq!(
+        handshake(
+            address,
+            "Sec-WebSocket-Protocol",
+            &format!("bearer, {}", API_KEY)
+        ),
+        Ok(Some("bearer".to_string()))
+    );
+}
+
+#[test]
+fn the_mock_rejects_missing_and_wrong_credentials() {
+    let address = start_mock_server();
+
+    assert!(handshake(address, "Authorization", "Bearer wrong-key").is_err());
+    assert!(handshake(address, "Authorization", "Basic bm9ib2R5").is_err());
+    assert!(handshake(address, "Sec-WebSocket-Protocol", "chat").is_err());
+}
@@ -0,0 +1,153 @@
+//! Forwards connections through the credential proxy to the mock listen server, checking that the
+//! proxy only lets in the clients it should, and that audio and transcripts get through.
+#![cfg(all(feature = "proxy", feature = "mock", not(target_arch = "wasm32")))]
+
+use jamfest::deepgram_mock::MockServer;
+use jamfest::deepgram_proxy::{ProxyConfig, ProxyServer, FORWARDED_PATH};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::http::HeaderValue;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+const API_KEY: &str = "proxy-test-key";
+const SECRET: &str = "proxy-test-secret";
+const SAMPLE_RATE: u32 = 16_000;
+
+/// Start the mock on a free port, and a proxy in front of it holding its key.
+fn start_proxy(secret: Option<&str>) -> SocketAddr {
+    let mock = MockServer::bind("127.0.0.1:0", Some(API_KEY.to_string()), None)
+        .expect("could not start the mock server");
+    let mock_address = mock.local_addr().expect("the mock server has no address");
+    thread::spawn(move || mock.run());
+
+    let mut config = ProxyConfig::new(&format!("ws://{}", mock_address), API_KEY);
+    config.secret = secret.map(str::to_string);
+    let proxy = ProxyServer::bind("127.0.0.1:0", config).expect("could not start the proxy");
+    let address = proxy.local_addr().expect("the proxy has no address");
+    thread::spawn(move || proxy.run());
+    address
+}
+
+/// Open a listen connection through the proxy with `headers` set, returning the socket and the
+/// subprotocol the proxy picked, if any.
+fn connect(
+    address: SocketAddr,
+    headers: &[(&'static str, &str)],
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Option<String>), String> {
+    let mut request = format!(
+        "ws://{}{}?encoding=linear16&sample_rate={}&callback=https://elsewhere.example",
+        address, FORWARDED_PATH, SAMPLE_RATE
+    )
+    .into_client_request()
+    .expect("the URL is valid");
+    for (name, value) in headers {
+        request.headers_mut().insert(
+            *name,
+            HeaderValue::from_str(value).expect("valid header value"),
+        );
+    }
+    let (socket, response) = tungstenite::connect(request).map_err(|error| error.to_string())?;
+    let protocol = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|protocol| protocol.to_str().ok())
+        .map(str::to_string);
+    Ok((socket, protocol))
+}
+
+/// Half a second of tone between two silences, which the mock hears as "sugar".
+fn burst() -> Vec<u8> {
+    let silence = |seconds: f32| vec![0i16; (SAMPLE_RATE as f32 * seconds) as usize];
+    let mut samples = silence(0.2);
+    samples.extend((0..SAMPLE_RATE / 2).map(|index| {
+        let t = index as f32 / SAMPLE_RATE as f32;
+        ((2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.3 * 32767.0) as i16
+    }));
+    samples.extend(silence(0.5));
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+#[test]
+fn audio_and_transcripts_go_through_the_proxy() {
+    let address = start_proxy(Some(SECRET));
+    let (mut socket, protocol) = connect(
+        address,
+        &[
+            ("Origin", "http://127.0.0.1:1334"),
+            ("Sec-WebSocket-Protocol", &format!("token, {}", SECRET)),
+        ],
+    )
+    .expect("the proxy should let the game in");
+    assert_eq!(protocol.as_deref(), Some("token"));
+
+    socket
+        .write_message(Message::Binary(burst()))
+        .expect("could not send the audio");
+    socket
+        .write_message(Message::Text(r#"{"type":"CloseStream"}"#.to_string()))
+        .expect("could not close the stream");
+
+    let mut transcripts = Vec::new();
+    loop {
+        match socket.read_message() {
+            Ok(Message::Text(message)) => {
+                let message: serde_json::Value =
+                    serde_json::from_str(&message).expect("the mock sends JSON");
+                if message["type"] == "Results" {
+                    transcripts.push(
+                        message["channel"]["alternatives"][0]["transcript"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string(),
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    assert!(
+        transcripts.iter().any(|transcript| transcript == "sugar"),
+        "heard {:?}",
+        transcripts
+    );
+}
+
+#[test]
+fn clients_need_the_secret() {
+    let address = start_proxy(Some(SECRET));
+
+    assert!(connect(address, &[("Authorization", &format!("Bearer {}", SECRET))]).is_ok());
+    assert!(connect(address, &[("Authorization", &format!("Token {}", API_KEY))]).is_err());
+    assert!(connect(address, &[("Sec-WebSocket-Protocol", "token, wrong")]).is_err());
+    assert!(connect(address, &[]).is_err());
+}
+
+#[test]
+fn only_allowed_origins_get_in() {
+    let address = start_proxy(None);
+
+    // clients that aren't browsers don't say where they're from
+    assert!(connect(address, &[]).is_ok());
+    assert!(connect(address, &[("Origin", "http://localhost:1334")]).is_ok());
+    let rejected = connect(address, &[("Origin", "https://elsewhere.example")]);
+    assert!(
+        rejected.as_ref().is_err_and(|error| error.contains("403")),
+        "{:?}",
+        rejected.map(|(_, protocol)| protocol)
+    );
+}
+
+#[test]
+fn offered_subprotocols_are_echoed_without_a_secret() {
+    let address = start_proxy(None);
+
+    let (_, protocol) = connect(address, &[("Sec-WebSocket-Protocol", "bearer, anything")])
+        .expect("the proxy should let the game in");
+    assert_eq!(protocol.as_deref(), Some("bearer"));
+}